@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS programs (
+                                        code TEXT PRIMARY KEY,
+                                        name TEXT NOT NULL,
+                                        places INTEGER NOT NULL DEFAULT 0,
+                                        subjects TEXT NOT NULL DEFAULT '["math","rus","phys"]'
+);
+
+INSERT OR IGNORE INTO programs (code, name, places) VALUES
+    ('ПМ', 'Прикладная математика', 40),
+    ('ИВТ', 'Информатика и ВТ', 50),
+    ('ИТСС', 'Связь и телекоммуникации', 30),
+    ('ИБ', 'Информационная безопасность', 20);
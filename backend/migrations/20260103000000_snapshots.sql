@@ -0,0 +1,43 @@
+CREATE TABLE IF NOT EXISTS snapshots (
+                                         id INTEGER PRIMARY KEY AUTOINCREMENT,
+                                         report_date TEXT NOT NULL UNIQUE,
+                                         created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Текущий список становится первым срезом
+INSERT INTO snapshots (report_date)
+SELECT COALESCE((SELECT MAX(record_date) FROM history_stats), date('now'))
+WHERE EXISTS (SELECT 1 FROM applicants);
+
+CREATE TABLE applicants_new (
+                                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                                snapshot_id INTEGER NOT NULL REFERENCES snapshots(id) ON DELETE CASCADE,
+                                external_id INTEGER NOT NULL,
+                                full_name TEXT NOT NULL,
+                                score_math INTEGER NOT NULL DEFAULT 0,
+                                score_rus INTEGER NOT NULL DEFAULT 0,
+                                score_phys INTEGER NOT NULL DEFAULT 0,
+                                score_achieve INTEGER NOT NULL DEFAULT 0,
+                                total_score INTEGER NOT NULL DEFAULT 0,
+                                agreed BOOLEAN NOT NULL DEFAULT 0,
+                                priorities TEXT NOT NULL,
+                                current_program TEXT,
+                                updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+
+                                UNIQUE(snapshot_id, external_id)
+);
+
+INSERT INTO applicants_new (
+    snapshot_id, external_id, full_name,
+    score_math, score_rus, score_phys, score_achieve, total_score,
+    agreed, priorities, current_program, updated_at
+)
+SELECT (SELECT MAX(id) FROM snapshots), external_id, full_name,
+       score_math, score_rus, score_phys, score_achieve, total_score,
+       agreed, priorities, current_program, updated_at
+FROM applicants;
+
+DROP TABLE applicants;
+ALTER TABLE applicants_new RENAME TO applicants;
+
+CREATE INDEX IF NOT EXISTS idx_applicants_snapshot_score ON applicants(snapshot_id, total_score DESC);
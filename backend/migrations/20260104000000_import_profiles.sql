@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS import_profiles (
+                                               name TEXT PRIMARY KEY,
+                                               columns TEXT NOT NULL DEFAULT '{}',
+                                               agreed_true TEXT NOT NULL DEFAULT '[]',
+                                               agreed_false TEXT NOT NULL DEFAULT '[]',
+                                               priority_separator TEXT
+);
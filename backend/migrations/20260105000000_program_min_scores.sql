@@ -0,0 +1,2 @@
+-- Минимальные баллы по предметам: {"math": 39, "rus": 40}
+ALTER TABLE programs ADD COLUMN min_scores TEXT NOT NULL DEFAULT '{}';
@@ -0,0 +1,12 @@
+-- Баллы по предметам хранятся открытым набором: {"math": 80, "rus": 75, "phys": 85, "inf": 90}
+ALTER TABLE applicants ADD COLUMN scores TEXT NOT NULL DEFAULT '{}';
+
+UPDATE applicants
+SET scores = json_object('math', score_math, 'rus', score_rus, 'phys', score_phys);
+
+ALTER TABLE applicants DROP COLUMN score_math;
+ALTER TABLE applicants DROP COLUMN score_rus;
+ALTER TABLE applicants DROP COLUMN score_phys;
+
+-- Третий предмет по умолчанию — физика или информатика на выбор
+UPDATE programs SET subjects = '["math","rus","phys|inf"]' WHERE subjects = '["math","rus","phys"]';
@@ -0,0 +1,6 @@
+-- Места по квотам: {"special": 4, "target": 6, "separate": 4}. Входят в общее число мест
+ALTER TABLE programs ADD COLUMN quotas TEXT NOT NULL DEFAULT '{}';
+
+-- Категория квоты, по которой подано заявление, и квота, по которой абитуриент прошел
+ALTER TABLE applicants ADD COLUMN quota TEXT;
+ALTER TABLE applicants ADD COLUMN admitted_quota TEXT;
@@ -0,0 +1,2 @@
+-- Зачисление без вступительных испытаний (победители и призеры олимпиад)
+ALTER TABLE applicants ADD COLUMN bvi BOOLEAN NOT NULL DEFAULT 0;
@@ -0,0 +1,2 @@
+-- Правила разрешения равенства баллов по порядку: "subject:<код>", "achievements", "priority"
+ALTER TABLE programs ADD COLUMN tie_break TEXT NOT NULL DEFAULT '["subject:math","subject:rus"]';
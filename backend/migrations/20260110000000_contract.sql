@@ -0,0 +1,12 @@
+-- Платные места (по договору) — отдельный конкурс после бюджетного
+ALTER TABLE programs ADD COLUMN contract_places INTEGER NOT NULL DEFAULT 0;
+
+-- Основания, на которые подано заявление, и согласие на зачисление по договору.
+-- Согласие на бюджет — по-прежнему `agreed`
+ALTER TABLE applicants ADD COLUMN budget BOOLEAN NOT NULL DEFAULT 1;
+ALTER TABLE applicants ADD COLUMN contract BOOLEAN NOT NULL DEFAULT 0;
+ALTER TABLE applicants ADD COLUMN contract_agreed BOOLEAN NOT NULL DEFAULT 0;
+
+-- Основание, по которому абитуриент прошел на `current_program`
+ALTER TABLE applicants ADD COLUMN admitted_basis TEXT;
+UPDATE applicants SET admitted_basis = 'budget' WHERE current_program IS NOT NULL;
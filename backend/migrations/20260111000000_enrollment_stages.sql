@@ -0,0 +1,23 @@
+-- Этапы зачисления по порядку сроков приема согласий
+CREATE TABLE IF NOT EXISTS enrollment_stages (
+    code TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    consent_deadline TEXT NOT NULL UNIQUE,
+    -- Доля мест общего конкурса, заполненная к концу этапа (нарастающим итогом, %)
+    places_percent INTEGER NOT NULL DEFAULT 100,
+    -- Приоритетный этап: только квоты и БВИ
+    priority_only BOOLEAN NOT NULL DEFAULT 0,
+    -- Дата среза, по которому зафиксировано зачисление; NULL — этап не закрыт
+    frozen_at TEXT
+);
+
+-- Зачисленные на закрытых этапах: в следующих пересчетах в конкурсе не участвуют и занимают места
+CREATE TABLE IF NOT EXISTS enrollments (
+    external_id INTEGER PRIMARY KEY,
+    stage_code TEXT NOT NULL REFERENCES enrollment_stages(code) ON DELETE CASCADE,
+    program_code TEXT NOT NULL,
+    quota TEXT,
+    basis TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_enrollments_stage ON enrollments(stage_code);
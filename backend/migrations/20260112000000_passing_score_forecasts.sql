@@ -0,0 +1,15 @@
+-- Число согласий на бюджет среди указавших направление — для прогноза проходного балла
+ALTER TABLE history_stats ADD COLUMN consents INTEGER NOT NULL DEFAULT 0;
+
+-- Прогнозы проходного балла на дату окончания приема согласий, по одному на каждый день пересчета
+CREATE TABLE IF NOT EXISTS passing_score_forecasts (
+    forecast_date TEXT NOT NULL,
+    program_code TEXT NOT NULL,
+    final_date TEXT NOT NULL,
+    passing_score INTEGER,
+    low INTEGER,
+    high INTEGER,
+    created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    UNIQUE(forecast_date, program_code, final_date)
+);
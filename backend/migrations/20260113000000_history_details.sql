@@ -0,0 +1,13 @@
+-- Полная статистика направления за день для графиков
+ALTER TABLE history_stats ADD COLUMN applications INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE history_stats ADD COLUMN count_priority_1 INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE history_stats ADD COLUMN count_priority_2 INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE history_stats ADD COLUMN count_priority_3 INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE history_stats ADD COLUMN count_priority_4 INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE history_stats ADD COLUMN enrolled_priority_1 INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE history_stats ADD COLUMN enrolled_priority_2 INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE history_stats ADD COLUMN enrolled_priority_3 INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE history_stats ADD COLUMN enrolled_priority_4 INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE history_stats ADD COLUMN competition_ratio REAL NOT NULL DEFAULT 0;
+ALTER TABLE history_stats ADD COLUMN avg_score REAL NOT NULL DEFAULT 0;
+ALTER TABLE history_stats ADD COLUMN median_score REAL NOT NULL DEFAULT 0;
@@ -0,0 +1,2 @@
+-- Место зачисленного в конкурсном рейтинге своего направления (с 1)
+ALTER TABLE applicants ADD COLUMN admission_rank INTEGER;
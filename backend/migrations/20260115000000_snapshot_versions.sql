@@ -0,0 +1,26 @@
+-- no-transaction
+-- Пересборка таблицы со ссылками из applicants: без внешних ключей, иначе DROP TABLE
+-- удалил бы абитуриентов каскадом
+PRAGMA foreign_keys = OFF;
+
+BEGIN;
+
+CREATE TABLE snapshots_new (
+                               id INTEGER PRIMARY KEY AUTOINCREMENT,
+                               report_date TEXT NOT NULL,
+    -- Версия среза за дату: повторный импорт за ту же дату создает следующую
+                               seq INTEGER NOT NULL DEFAULT 1,
+                               created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+                               UNIQUE(report_date, seq)
+);
+
+INSERT INTO snapshots_new (id, report_date, seq, created_at)
+SELECT id, report_date, 1, created_at FROM snapshots;
+
+DROP TABLE snapshots;
+ALTER TABLE snapshots_new RENAME TO snapshots;
+
+COMMIT;
+
+PRAGMA foreign_keys = ON;
@@ -1,51 +1,71 @@
-use crate::models::{Applicant, Scores};
-use sqlx::{QueryBuilder, Row, Sqlite, SqlitePool};
+use crate::models::{
+    Applicant, Enrollment, EnrollmentStage, ForecastAccuracy, FundingBasis, HistoryPoint, ImportMode, ImportProfile,
+    PassingScoreForecast, Program, ProgramStats, ProgramUpdate, Scores, Snapshot, StageUpdate,
+};
+use sqlx::{QueryBuilder, Row, Sqlite, SqliteConnection, SqlitePool};
+use std::collections::BTreeMap;
 
-pub async fn get_applicants(
-    pool: &SqlitePool,
-    limit: i32,
-    offset: i32,
-    search: Option<String>,
-    agreed: Option<bool>,
-    program: Option<String>,
-    min_score: Option<i32>,
-) -> Result<Vec<Applicant>, sqlx::Error> {
-    
-    let mut builder: QueryBuilder<Sqlite> = QueryBuilder::new(
-        r#"
-        SELECT id, full_name, agreed, total_score, 
-               score_math, score_rus, score_phys, score_achieve, 
-               priorities, current_program
-        FROM applicants
-        WHERE 1=1
-        "#
-    );
+/// Фильтры списка абитуриентов внутри одного среза.
+#[derive(Default, Clone)]
+pub struct ApplicantFilter {
+    pub search: Option<String>,
+    pub agreed: Option<bool>,
+    pub program: Option<String>,
+    pub min_score: Option<i32>,
+}
 
-    if let Some(s) = &search {
-        if !s.is_empty() {
-             builder.push(" AND full_name LIKE ");
-             builder.push_bind(format!("%{}%", s));
-        }
+fn push_filter(builder: &mut QueryBuilder<Sqlite>, filter: &ApplicantFilter) {
+    if let Some(s) = &filter.search
+        && !s.is_empty()
+    {
+        builder.push(" AND full_name LIKE ");
+        builder.push_bind(format!("%{}%", s));
     }
 
-    if let Some(a) = agreed {
+    if let Some(a) = filter.agreed {
         builder.push(" AND agreed = ");
         builder.push_bind(a);
     }
 
-    if let Some(p) = &program {
-        if !p.is_empty() {
-            builder.push(" AND current_program = ");
-            builder.push_bind(p);
-        }
+    if let Some(p) = &filter.program
+        && !p.is_empty()
+    {
+        builder.push(" AND current_program = ");
+        builder.push_bind(p.clone());
     }
 
-    if let Some(min) = min_score {
+    if let Some(min) = filter.min_score {
         builder.push(" AND total_score >= ");
         builder.push_bind(min);
     }
+}
 
-    builder.push(" ORDER BY total_score DESC LIMIT ");
+pub async fn get_applicants(
+    pool: &SqlitePool,
+    snapshot_id: i64,
+    filter: &ApplicantFilter,
+    limit: i32,
+    offset: i32,
+) -> Result<Vec<Applicant>, sqlx::Error> {
+    
+    let mut builder: QueryBuilder<Sqlite> = QueryBuilder::new(
+        r#"
+        SELECT id, external_id, full_name, agreed, bvi, total_score, 
+               scores, score_achieve, budget, contract, contract_agreed,
+               priorities, current_program, quota, admitted_quota, admitted_basis
+        FROM applicants
+        WHERE snapshot_id = "#
+    );
+    builder.push_bind(snapshot_id);
+
+    push_filter(&mut builder, filter);
+
+    // Зачисленные на направление — в порядке его конкурсного рейтинга
+    if filter.program.as_deref().is_some_and(|p| !p.is_empty()) {
+        builder.push(" ORDER BY admission_rank ASC, id ASC LIMIT ");
+    } else {
+        builder.push(" ORDER BY total_score DESC, id ASC LIMIT ");
+    }
     builder.push_bind(limit);
     builder.push(" OFFSET ");
     builder.push_bind(offset);
@@ -57,20 +77,28 @@ pub async fn get_applicants(
         .map(|row| {
             let priorities_str: String = row.get("priorities");
             let priorities: Vec<String> = serde_json::from_str(&priorities_str).unwrap_or_default();
+            let scores_str: String = row.get("scores");
+            let admitted_basis: Option<String> = row.get("admitted_basis");
 
             Applicant {
                 id: row.get("id"),
+                external_id: row.get("external_id"),
                 full_name: row.get("full_name"),
                 agreed: row.get("agreed"),
+                budget: row.get("budget"),
+                contract: row.get("contract"),
+                contract_agreed: row.get("contract_agreed"),
+                bvi: row.get("bvi"),
                 total_score: row.get("total_score"),
                 scores: Scores {
-                    math: row.get("score_math"),
-                    rus: row.get("score_rus"),
-                    phys: row.get("score_phys"),
+                    subjects: serde_json::from_str(&scores_str).unwrap_or_default(),
                     achievements: row.get("score_achieve"),
                 },
                 current_program: row.get("current_program"),
                 priorities,
+                quota: row.get("quota"),
+                admitted_quota: row.get("admitted_quota"),
+                admitted_basis: admitted_basis.as_deref().and_then(FundingBasis::parse),
             }
         })
         .collect();
@@ -78,38 +106,21 @@ pub async fn get_applicants(
     Ok(applicants)
 }
 
+/// Весь срез целиком (для расчетов).
+pub async fn get_all_applicants(pool: &SqlitePool, snapshot_id: i64) -> Result<Vec<Applicant>, sqlx::Error> {
+    get_applicants(pool, snapshot_id, &ApplicantFilter::default(), i32::MAX, 0).await
+}
+
 pub async fn count_applicants(
     pool: &SqlitePool, 
-    search: Option<String>,
-    agreed: Option<bool>,
-    program: Option<String>,
-    min_score: Option<i32>,
+    snapshot_id: i64,
+    filter: &ApplicantFilter,
 ) -> Result<i64, sqlx::Error> {
-    let mut builder: QueryBuilder<Sqlite> = QueryBuilder::new("SELECT COUNT(*) FROM applicants WHERE 1=1");
-
-    if let Some(s) = &search {
-        if !s.is_empty() {
-             builder.push(" AND full_name LIKE ");
-             builder.push_bind(format!("%{}%", s));
-        }
-    }
-
-    if let Some(a) = agreed {
-        builder.push(" AND agreed = ");
-        builder.push_bind(a);
-    }
-
-    if let Some(p) = &program {
-        if !p.is_empty() {
-            builder.push(" AND current_program = ");
-            builder.push_bind(p);
-        }
-    }
+    let mut builder: QueryBuilder<Sqlite> =
+        QueryBuilder::new("SELECT COUNT(*) FROM applicants WHERE snapshot_id = ");
+    builder.push_bind(snapshot_id);
 
-    if let Some(min) = min_score {
-        builder.push(" AND total_score >= ");
-        builder.push_bind(min);
-    }
+    push_filter(&mut builder, filter);
 
     let count: (i64,) = builder.build_query_as().fetch_one(pool).await?;
     Ok(count.0)
@@ -118,18 +129,143 @@ pub async fn count_applicants(
 pub struct NewApplicant {
     pub external_id: i32,
     pub full_name: String,
-    pub score_math: i32,
-    pub score_rus: i32,
-    pub score_phys: i32,
+    // Код предмета -> балл; предметов, которые абитуриент не сдавал, нет
+    pub scores: BTreeMap<String, i32>,
     pub score_achieve: i32,
     pub agreed: bool,
+    pub budget: bool,
+    pub contract: bool,
+    pub contract_agreed: bool,
+    pub bvi: bool,
     pub priorities: Vec<String>,
+    pub quota: Option<String>,
+}
+
+impl NewApplicant {
+    pub fn total_score(&self) -> i32 {
+        self.to_scores().total()
+    }
+
+    fn to_scores(&self) -> Scores {
+        Scores {
+            subjects: self.scores.clone(),
+            achievements: self.score_achieve,
+        }
+    }
+
+    /// Запись в том виде, в каком она окажется в БД после импорта (без распределения).
+    pub fn to_applicant(&self, id: i32) -> Applicant {
+        Applicant {
+            id,
+            external_id: self.external_id,
+            full_name: self.full_name.clone(),
+            agreed: self.agreed,
+            budget: self.budget,
+            contract: self.contract,
+            contract_agreed: self.contract_agreed,
+            bvi: self.bvi,
+            total_score: self.total_score(),
+            scores: self.to_scores(),
+            current_program: None,
+            priorities: self.priorities.clone(),
+            quota: self.quota.clone(),
+            admitted_quota: None,
+            admitted_basis: None,
+        }
+    }
 }
+
+/// Сохраняет импорт новым срезом за `report_date` и возвращает его id.
+/// Срезы не изменяются: повторный импорт за ту же дату создает следующую версию
+/// (`seq`), актуальной за дату считается последняя.
+///
+/// Новый срез строится от актуальной версии за эту дату или последнего среза до нее:
+/// - `Replace`: срез содержит строки файла; абитуриенты из `kept_ids`
+///   (строки файла с ошибками) остаются как в предыдущем срезе;
+/// - `Merge`: строки файла добавляются/обновляются, остальные остаются;
+/// - `Delete`: из среза удаляются перечисленные `external_id`.
 pub async fn import_batch(
     pool: &SqlitePool,
+    report_date: &str,
+    mode: ImportMode,
     applicants: Vec<NewApplicant>,
-) -> Result<(), sqlx::Error> {
+    kept_ids: &[i32],
+) -> Result<i64, sqlx::Error> {
     let mut tx = pool.begin().await?;
+
+    let base_id: Option<i64> = sqlx::query_scalar(
+        "SELECT id FROM snapshots WHERE report_date <= ? ORDER BY report_date DESC, seq DESC LIMIT 1",
+    )
+    .bind(report_date)
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let snapshot_id: i64 = sqlx::query_scalar(
+        r#"
+        INSERT INTO snapshots (report_date, seq)
+        VALUES (?, (SELECT COALESCE(MAX(seq), 0) + 1 FROM snapshots WHERE report_date = ?))
+        RETURNING id
+        "#,
+    )
+    .bind(report_date)
+    .bind(report_date)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    if let Some(base_id) = base_id {
+        copy_snapshot(&mut tx, base_id, snapshot_id).await?;
+    }
+
+    match mode {
+        ImportMode::Replace => {
+            let ids: Vec<i32> = applicants.iter().map(|a| a.external_id).chain(kept_ids.iter().copied()).collect();
+            upsert_applicants(&mut tx, snapshot_id, &applicants).await?;
+            delete_missing_applicants(&mut tx, snapshot_id, &ids).await?;
+        }
+        ImportMode::Merge => {
+            upsert_applicants(&mut tx, snapshot_id, &applicants).await?;
+        }
+        ImportMode::Delete => {
+            let ids: Vec<i32> = applicants.iter().map(|a| a.external_id).collect();
+            delete_applicants(&mut tx, snapshot_id, &ids).await?;
+        }
+    }
+
+    tx.commit().await?;
+
+    Ok(snapshot_id)
+}
+
+async fn copy_snapshot(conn: &mut SqliteConnection, from_id: i64, snapshot_id: i64) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO applicants (
+            snapshot_id, external_id, full_name,
+            scores, score_achieve, total_score,
+            agreed, budget, contract, contract_agreed, bvi, priorities, quota,
+            current_program, admitted_quota, admitted_basis, admission_rank, updated_at
+        )
+        SELECT ?, external_id, full_name,
+               scores, score_achieve, total_score,
+               agreed, budget, contract, contract_agreed, bvi, priorities, quota,
+               current_program, admitted_quota, admitted_basis, admission_rank, updated_at
+        FROM applicants
+        WHERE snapshot_id = ?
+        "#,
+    )
+    .bind(snapshot_id)
+    .bind(from_id)
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+async fn upsert_applicants(
+    conn: &mut SqliteConnection,
+    snapshot_id: i64,
+    applicants: &[NewApplicant],
+) -> Result<(), sqlx::Error> {
     let now = chrono::Local::now().naive_local();
 
     const BATCH_SIZE: usize = 500;
@@ -137,144 +273,782 @@ pub async fn import_batch(
     for chunk in applicants.chunks(BATCH_SIZE) {
         let mut query_builder: QueryBuilder<Sqlite> = QueryBuilder::new(
             r#"INSERT INTO applicants (
-                external_id, full_name, 
-                score_math, score_rus, score_phys, score_achieve, total_score,
-                agreed, priorities, updated_at
+                snapshot_id, external_id, full_name, 
+                scores, score_achieve, total_score,
+                agreed, budget, contract, contract_agreed, bvi, priorities, quota, updated_at
             ) "#,
         );
 
         query_builder.push_values(chunk, |mut b, p| {
-            let total = p.score_math + p.score_rus + p.score_phys + p.score_achieve;
+            let total = p.total_score();
             let priorities_json =
                 serde_json::to_string(&p.priorities).unwrap_or_else(|_| "[]".to_string());
+            let scores_json = serde_json::to_string(&p.scores).unwrap_or_else(|_| "{}".to_string());
 
-            b.push_bind(p.external_id)
+            b.push_bind(snapshot_id)
+                .push_bind(p.external_id)
                 .push_bind(&p.full_name)
-                .push_bind(p.score_math)
-                .push_bind(p.score_rus)
-                .push_bind(p.score_phys)
+                .push_bind(scores_json)
                 .push_bind(p.score_achieve)
                 .push_bind(total)
                 .push_bind(p.agreed)
+                .push_bind(p.budget)
+                .push_bind(p.contract)
+                .push_bind(p.contract_agreed)
+                .push_bind(p.bvi)
                 .push_bind(priorities_json)
+                .push_bind(&p.quota)
                 .push_bind(now);
         });
 
         query_builder.push(
-            r#" ON CONFLICT(external_id) DO UPDATE SET
+            r#" ON CONFLICT(snapshot_id, external_id) DO UPDATE SET
                 full_name = excluded.full_name,
-                score_math = excluded.score_math,
-                score_rus = excluded.score_rus,
-                score_phys = excluded.score_phys,
+                scores = excluded.scores,
                 score_achieve = excluded.score_achieve,
                 total_score = excluded.total_score,
                 agreed = excluded.agreed,
+                budget = excluded.budget,
+                contract = excluded.contract,
+                contract_agreed = excluded.contract_agreed,
+                bvi = excluded.bvi,
                 priorities = excluded.priorities,
+                quota = excluded.quota,
                 updated_at = excluded.updated_at
             "#,
         );
 
         let query = query_builder.build();
-        query.execute(&mut *tx).await?;
+        query.execute(&mut *conn).await?;
     }
 
-    sqlx::query("DELETE FROM applicants WHERE updated_at < ?")
-        .bind(now)
+    Ok(())
+}
+
+/// Зачисление одного абитуриента для `save_allocation`.
+pub struct AdmissionRecord<'a> {
+    // Внутренний `applicants.id`
+    pub id: i32,
+    pub program: &'a str,
+    pub quota: Option<&'a str>,
+    pub basis: FundingBasis,
+    // Место в рейтинге направления среди зачисленных на него
+    pub rank: i32,
+}
+
+/// Записывает результат распределения среза и историю за `date` одной транзакцией:
+/// сброс зачисления, пакетное проставление, upsert статистики направлений в `history_stats`
+/// и прогнозов проходного балла за этот день.
+pub async fn save_allocation(
+    pool: &SqlitePool,
+    snapshot_id: i64,
+    admissions: &[AdmissionRecord<'_>],
+    date: &str,
+    history: &[ProgramStats],
+    forecasts: &[PassingScoreForecast],
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query(
+        "UPDATE applicants SET current_program = NULL, admitted_quota = NULL, admitted_basis = NULL, admission_rank = NULL WHERE snapshot_id = ?",
+    )
+        .bind(snapshot_id)
         .execute(&mut *tx)
         .await?;
 
-    tx.commit().await?;
+    sqlx::query(
+        "CREATE TEMP TABLE IF NOT EXISTS admission_results (id INTEGER PRIMARY KEY, program TEXT NOT NULL, quota TEXT, basis TEXT NOT NULL, rank INTEGER NOT NULL)",
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query("DELETE FROM admission_results").execute(&mut *tx).await?;
+
+    for chunk in admissions.chunks(500) {
+        let mut builder: QueryBuilder<Sqlite> =
+            QueryBuilder::new("INSERT INTO admission_results (id, program, quota, basis, rank) ");
+        builder.push_values(chunk, |mut b, a| {
+            b.push_bind(a.id).push_bind(a.program).push_bind(a.quota).push_bind(a.basis.as_str()).push_bind(a.rank);
+        });
+        builder.build().execute(&mut *tx).await?;
+    }
 
-    Ok(())
-}
-pub async fn upsert_applicant(pool: &SqlitePool, person: &NewApplicant) -> Result<(), sqlx::Error> {
-    let total = person.score_math + person.score_rus + person.score_phys + person.score_achieve;
-    let priorities_json = serde_json::to_string(&person.priorities).unwrap();
     sqlx::query(
         r#"
-        INSERT INTO applicants (
-            external_id, full_name, 
-            score_math, score_rus, score_phys, score_achieve, total_score,
-            agreed, priorities, updated_at
-        )
-        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, CURRENT_TIMESTAMP)
-        ON CONFLICT(external_id) DO UPDATE SET
-            full_name = excluded.full_name,
-            score_math = excluded.score_math,
-            score_rus = excluded.score_rus,
-            score_phys = excluded.score_phys,
-            score_achieve = excluded.score_achieve,
-            total_score = excluded.total_score,
-            agreed = excluded.agreed,
-            priorities = excluded.priorities,
-            updated_at = CURRENT_TIMESTAMP
+        UPDATE applicants SET current_program = r.program, admitted_quota = r.quota, admitted_basis = r.basis,
+            admission_rank = r.rank
+        FROM admission_results AS r
+        WHERE applicants.id = r.id
         "#,
     )
-    .bind(person.external_id)
-    .bind(&person.full_name)
-    .bind(person.score_math)
-    .bind(person.score_rus)
-    .bind(person.score_phys)
-    .bind(person.score_achieve)
-    .bind(total)
-    .bind(person.agreed)
-    .bind(priorities_json)
-    .execute(pool)
+    .execute(&mut *tx)
     .await?;
+    sqlx::query("DELETE FROM admission_results").execute(&mut *tx).await?;
+
+    for chunk in history.chunks(500) {
+        let mut builder: QueryBuilder<Sqlite> =
+            QueryBuilder::new(
+                r#"INSERT INTO history_stats (
+                    record_date, program_code, passing_score, places_filled, applications, consents,
+                    competition_ratio, avg_score, median_score,
+                    count_priority_1, count_priority_2, count_priority_3, count_priority_4,
+                    enrolled_priority_1, enrolled_priority_2, enrolled_priority_3, enrolled_priority_4
+                ) "#,
+            );
+        builder.push_values(chunk, |mut b, h| {
+            b.push_bind(date)
+                .push_bind(&h.program_code)
+                .push_bind(h.passing_score)
+                .push_bind(h.places_filled)
+                .push_bind(h.applications)
+                .push_bind(h.consents)
+                .push_bind(h.competition_ratio)
+                .push_bind(h.avg_score)
+                .push_bind(h.median_score)
+                .push_bind(h.count_priority_1)
+                .push_bind(h.count_priority_2)
+                .push_bind(h.count_priority_3)
+                .push_bind(h.count_priority_4)
+                .push_bind(h.enrolled_priority_1)
+                .push_bind(h.enrolled_priority_2)
+                .push_bind(h.enrolled_priority_3)
+                .push_bind(h.enrolled_priority_4);
+        });
+        builder.push(
+            r#" ON CONFLICT(record_date, program_code) DO UPDATE SET
+                passing_score = excluded.passing_score,
+                places_filled = excluded.places_filled,
+                applications = excluded.applications,
+                consents = excluded.consents,
+                competition_ratio = excluded.competition_ratio,
+                avg_score = excluded.avg_score,
+                median_score = excluded.median_score,
+                count_priority_1 = excluded.count_priority_1,
+                count_priority_2 = excluded.count_priority_2,
+                count_priority_3 = excluded.count_priority_3,
+                count_priority_4 = excluded.count_priority_4,
+                enrolled_priority_1 = excluded.enrolled_priority_1,
+                enrolled_priority_2 = excluded.enrolled_priority_2,
+                enrolled_priority_3 = excluded.enrolled_priority_3,
+                enrolled_priority_4 = excluded.enrolled_priority_4
+            "#,
+        );
+        builder.build().execute(&mut *tx).await?;
+    }
+
+    insert_passing_forecasts(&mut tx, forecasts).await?;
 
+    tx.commit().await?;
     Ok(())
 }
 
-pub async fn reset_admission_status(pool: &SqlitePool) -> Result<(), sqlx::Error> {
-    sqlx::query("UPDATE applicants SET current_program = NULL")
+async fn delete_missing_applicants(
+    conn: &mut SqliteConnection,
+    snapshot_id: i64,
+    present_external_ids: &[i32],
+) -> Result<(), sqlx::Error> {
+    if present_external_ids.is_empty() {
+        sqlx::query("DELETE FROM applicants WHERE snapshot_id = ?")
+            .bind(snapshot_id)
+            .execute(&mut *conn)
+            .await?;
+        return Ok(());
+    }
+
+    // Список id может быть длиннее лимита параметров SQLite, поэтому через временную таблицу
+    sqlx::query("CREATE TEMP TABLE IF NOT EXISTS import_ids (external_id INTEGER PRIMARY KEY)")
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query("DELETE FROM import_ids").execute(&mut *conn).await?;
+
+    for chunk in present_external_ids.chunks(500) {
+        let mut builder: QueryBuilder<Sqlite> =
+            QueryBuilder::new("INSERT OR IGNORE INTO import_ids (external_id) ");
+        builder.push_values(chunk, |mut b, id| {
+            b.push_bind(*id);
+        });
+        builder.build().execute(&mut *conn).await?;
+    }
+
+    sqlx::query(
+        "DELETE FROM applicants WHERE snapshot_id = ? AND external_id NOT IN (SELECT external_id FROM import_ids)",
+    )
+    .bind(snapshot_id)
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query("DELETE FROM import_ids").execute(&mut *conn).await?;
+
+    Ok(())
+}
+
+async fn delete_applicants(
+    conn: &mut SqliteConnection,
+    snapshot_id: i64,
+    external_ids: &[i32],
+) -> Result<(), sqlx::Error> {
+    for chunk in external_ids.chunks(500) {
+        let mut builder: QueryBuilder<Sqlite> =
+            QueryBuilder::new("DELETE FROM applicants WHERE snapshot_id = ");
+        builder.push_bind(snapshot_id);
+        builder.push(" AND external_id IN (");
+
+        let mut separated = builder.separated(", ");
+        for id in chunk {
+            separated.push_bind(*id);
+        }
+        separated.push_unseparated(")");
+
+        builder.build().execute(&mut *conn).await?;
+    }
+
+    Ok(())
+}
+
+pub async fn clear_all(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query("DELETE FROM applicants").execute(&mut *tx).await?;
+    sqlx::query("DELETE FROM history_stats").execute(&mut *tx).await?;
+    sqlx::query("DELETE FROM passing_score_forecasts").execute(&mut *tx).await?;
+    sqlx::query("DELETE FROM snapshots").execute(&mut *tx).await?;
+    // Зачисление этапов фиксировалось по удаленным срезам
+    sqlx::query("DELETE FROM enrollments").execute(&mut *tx).await?;
+    sqlx::query("UPDATE enrollment_stages SET frozen_at = NULL").execute(&mut *tx).await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+fn program_from_row(row: sqlx::sqlite::SqliteRow) -> Program {
+    let subjects_str: String = row.get("subjects");
+    let min_scores_str: String = row.get("min_scores");
+    let quotas_str: String = row.get("quotas");
+    let tie_break_str: String = row.get("tie_break");
+
+    Program {
+        code: row.get("code"),
+        name: row.get("name"),
+        places: row.get("places"),
+        contract_places: row.get("contract_places"),
+        subjects: serde_json::from_str(&subjects_str).unwrap_or_default(),
+        min_scores: serde_json::from_str(&min_scores_str).unwrap_or_default(),
+        quotas: serde_json::from_str(&quotas_str).unwrap_or_default(),
+        tie_break: serde_json::from_str(&tie_break_str).unwrap_or_default(),
+    }
+}
+
+pub async fn list_programs(pool: &SqlitePool) -> Result<Vec<Program>, sqlx::Error> {
+    let rows = sqlx::query("SELECT code, name, places, contract_places, subjects, min_scores, quotas, tie_break FROM programs ORDER BY code")
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows.into_iter().map(program_from_row).collect())
+}
+
+pub async fn get_program(pool: &SqlitePool, code: &str) -> Result<Option<Program>, sqlx::Error> {
+    let row = sqlx::query("SELECT code, name, places, contract_places, subjects, min_scores, quotas, tie_break FROM programs WHERE code = ?")
+        .bind(code)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(row.map(program_from_row))
+}
+
+pub async fn insert_program(pool: &SqlitePool, program: &Program) -> Result<(), sqlx::Error> {
+    let subjects_json = serde_json::to_string(&program.subjects).unwrap_or_else(|_| "[]".to_string());
+    let min_scores_json = serde_json::to_string(&program.min_scores).unwrap_or_else(|_| "{}".to_string());
+    let quotas_json = serde_json::to_string(&program.quotas).unwrap_or_else(|_| "{}".to_string());
+    let tie_break_json = serde_json::to_string(&program.tie_break).unwrap_or_else(|_| "[]".to_string());
+
+    sqlx::query(
+        "INSERT INTO programs (code, name, places, contract_places, subjects, min_scores, quotas, tie_break) VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+        .bind(&program.code)
+        .bind(&program.name)
+        .bind(program.places)
+        .bind(program.contract_places)
+        .bind(subjects_json)
+        .bind(min_scores_json)
+        .bind(quotas_json)
+        .bind(tie_break_json)
         .execute(pool)
         .await?;
+
     Ok(())
 }
 
-pub async fn set_admission_program(
+pub async fn update_program(
     pool: &SqlitePool,
-    applicant_internal_id: i32,
-    program: &str,
-) -> Result<(), sqlx::Error> {
-    sqlx::query("UPDATE applicants SET current_program = ? WHERE id = ?")
-        .bind(program)
-        .bind(applicant_internal_id)
+    code: &str,
+    program: &ProgramUpdate,
+) -> Result<bool, sqlx::Error> {
+    let subjects_json = serde_json::to_string(&program.subjects).unwrap_or_else(|_| "[]".to_string());
+    let min_scores_json = serde_json::to_string(&program.min_scores).unwrap_or_else(|_| "{}".to_string());
+    let quotas_json = serde_json::to_string(&program.quotas).unwrap_or_else(|_| "{}".to_string());
+    let tie_break_json = serde_json::to_string(&program.tie_break).unwrap_or_else(|_| "[]".to_string());
+
+    let result = sqlx::query(
+        "UPDATE programs SET name = ?, places = ?, contract_places = ?, subjects = ?, min_scores = ?, quotas = ?, tie_break = ? WHERE code = ?",
+    )
+        .bind(&program.name)
+        .bind(program.places)
+        .bind(program.contract_places)
+        .bind(subjects_json)
+        .bind(min_scores_json)
+        .bind(quotas_json)
+        .bind(tie_break_json)
+        .bind(code)
         .execute(pool)
         .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn delete_program(pool: &SqlitePool, code: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM programs WHERE code = ?")
+        .bind(code)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+fn stage_from_row(row: sqlx::sqlite::SqliteRow) -> EnrollmentStage {
+    EnrollmentStage {
+        code: row.get("code"),
+        name: row.get("name"),
+        consent_deadline: row.get("consent_deadline"),
+        places_percent: row.get("places_percent"),
+        priority_only: row.get("priority_only"),
+        frozen_at: row.get("frozen_at"),
+        enrolled: row.get("enrolled"),
+    }
+}
+
+const STAGE_SELECT: &str = r#"
+    SELECT s.code, s.name, s.consent_deadline, s.places_percent, s.priority_only, s.frozen_at,
+           (SELECT COUNT(*) FROM enrollments e WHERE e.stage_code = s.code) AS enrolled
+    FROM enrollment_stages s
+"#;
+
+/// Этапы зачисления по сроку приема согласий.
+pub async fn list_stages(pool: &SqlitePool) -> Result<Vec<EnrollmentStage>, sqlx::Error> {
+    let mut builder: QueryBuilder<Sqlite> = QueryBuilder::new(STAGE_SELECT);
+    builder.push(" ORDER BY s.consent_deadline ASC");
+
+    let rows = builder.build().fetch_all(pool).await?;
+
+    Ok(rows.into_iter().map(stage_from_row).collect())
+}
+
+pub async fn insert_stage(pool: &SqlitePool, stage: &EnrollmentStage) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO enrollment_stages (code, name, consent_deadline, places_percent, priority_only) VALUES (?, ?, ?, ?, ?)",
+    )
+        .bind(&stage.code)
+        .bind(&stage.name)
+        .bind(&stage.consent_deadline)
+        .bind(stage.places_percent)
+        .bind(stage.priority_only)
+        .execute(pool)
+        .await?;
+
     Ok(())
 }
 
-pub async fn delete_missing_applicants(
+pub async fn update_stage(pool: &SqlitePool, code: &str, stage: &StageUpdate) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE enrollment_stages SET name = ?, consent_deadline = ?, places_percent = ?, priority_only = ? WHERE code = ?",
+    )
+        .bind(&stage.name)
+        .bind(&stage.consent_deadline)
+        .bind(stage.places_percent)
+        .bind(stage.priority_only)
+        .bind(code)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn delete_stage(pool: &SqlitePool, code: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM enrollment_stages WHERE code = ?")
+        .bind(code)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn list_enrollments(pool: &SqlitePool) -> Result<Vec<Enrollment>, sqlx::Error> {
+    let rows = sqlx::query("SELECT stage_code, external_id, program_code, quota, basis FROM enrollments ORDER BY external_id")
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            let basis: String = row.get("basis");
+            Enrollment {
+                stage_code: row.get("stage_code"),
+                external_id: row.get("external_id"),
+                program_code: row.get("program_code"),
+                quota: row.get("quota"),
+                basis: FundingBasis::parse(&basis).unwrap_or_default(),
+            }
+        })
+        .collect())
+}
+
+/// Закрывает этап: записывает зачисленных по срезу за `report_date` одной транзакцией.
+/// Зафиксированные на прошлых этапах сохраняют свою запись.
+pub async fn freeze_stage(
     pool: &SqlitePool,
-    present_external_ids: &[i32],
+    code: &str,
+    report_date: &str,
+    enrollments: &[Enrollment],
 ) -> Result<(), sqlx::Error> {
-    if present_external_ids.is_empty() {
-        sqlx::query("DELETE FROM applicants").execute(pool).await?;
-        return Ok(());
+    let mut tx = pool.begin().await?;
+
+    for chunk in enrollments.chunks(500) {
+        let mut builder: QueryBuilder<Sqlite> =
+            QueryBuilder::new("INSERT OR IGNORE INTO enrollments (external_id, stage_code, program_code, quota, basis) ");
+        builder.push_values(chunk, |mut b, e| {
+            b.push_bind(e.external_id)
+                .push_bind(&e.stage_code)
+                .push_bind(&e.program_code)
+                .push_bind(&e.quota)
+                .push_bind(e.basis.as_str());
+        });
+        builder.build().execute(&mut *tx).await?;
     }
 
-    let mut builder: QueryBuilder<Sqlite> =
-        QueryBuilder::new("DELETE FROM applicants WHERE external_id NOT IN (");
+    sqlx::query("UPDATE enrollment_stages SET frozen_at = ? WHERE code = ?")
+        .bind(report_date)
+        .bind(code)
+        .execute(&mut *tx)
+        .await?;
 
-    let mut separated = builder.separated(", ");
-    for id in present_external_ids {
-        separated.push_bind(id);
+    tx.commit().await?;
+    Ok(())
+}
+
+/// История `history_stats` по направлениям в порядке дат, до `up_to` включительно.
+pub async fn get_history(pool: &SqlitePool, up_to: Option<&str>) -> Result<BTreeMap<String, Vec<HistoryPoint>>, sqlx::Error> {
+    let mut builder: QueryBuilder<Sqlite> = QueryBuilder::new(
+        r#"SELECT program_code, record_date, passing_score, places_filled, applications, consents,
+                  competition_ratio, avg_score, median_score,
+                  count_priority_1, count_priority_2, count_priority_3, count_priority_4,
+                  enrolled_priority_1, enrolled_priority_2, enrolled_priority_3, enrolled_priority_4
+           FROM history_stats"#,
+    );
+    if let Some(date) = up_to {
+        builder.push(" WHERE record_date <= ");
+        builder.push_bind(date);
     }
-    separated.push_unseparated(")");
+    builder.push(" ORDER BY record_date ASC");
+
+    let rows = builder.build().fetch_all(pool).await?;
 
-    let query = builder.build();
-    query.execute(pool).await?;
+    let mut history: BTreeMap<String, Vec<HistoryPoint>> = BTreeMap::new();
+    for row in rows {
+        history.entry(row.get("program_code")).or_default().push(HistoryPoint {
+            date: row.get("record_date"),
+            passing_score: row.get("passing_score"),
+            places_filled: row.get("places_filled"),
+            applications: row.get("applications"),
+            consents: row.get("consents"),
+            competition_ratio: row.get("competition_ratio"),
+            avg_score: row.get("avg_score"),
+            median_score: row.get("median_score"),
+            count_priority_1: row.get("count_priority_1"),
+            count_priority_2: row.get("count_priority_2"),
+            count_priority_3: row.get("count_priority_3"),
+            count_priority_4: row.get("count_priority_4"),
+            enrolled_priority_1: row.get("enrolled_priority_1"),
+            enrolled_priority_2: row.get("enrolled_priority_2"),
+            enrolled_priority_3: row.get("enrolled_priority_3"),
+            enrolled_priority_4: row.get("enrolled_priority_4"),
+        });
+    }
+    Ok(history)
+}
 
+/// Сохраняет прогнозы проходного балла; прогноз за тот же день и срок перезаписывается.
+async fn insert_passing_forecasts(
+    conn: &mut SqliteConnection,
+    forecasts: &[PassingScoreForecast],
+) -> Result<(), sqlx::Error> {
+    for chunk in forecasts.chunks(500) {
+        let mut builder: QueryBuilder<Sqlite> = QueryBuilder::new(
+            "INSERT INTO passing_score_forecasts (forecast_date, program_code, final_date, passing_score, low, high) ",
+        );
+        builder.push_values(chunk, |mut b, f| {
+            b.push_bind(&f.forecast_date)
+                .push_bind(&f.program_code)
+                .push_bind(&f.final_date)
+                .push_bind(f.passing_score)
+                .push_bind(f.low)
+                .push_bind(f.high);
+        });
+        builder.push(
+            r#" ON CONFLICT(forecast_date, program_code, final_date) DO UPDATE SET
+                passing_score = excluded.passing_score,
+                low = excluded.low,
+                high = excluded.high,
+                created_at = CURRENT_TIMESTAMP
+            "#,
+        );
+        builder.build().execute(&mut *conn).await?;
+    }
     Ok(())
 }
 
-pub async fn clear_all(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+/// Сохраненные прогнозы с фактическим проходным баллом из истории за дату окончания.
+pub async fn list_forecast_accuracy(pool: &SqlitePool) -> Result<Vec<ForecastAccuracy>, sqlx::Error> {
+    let rows = sqlx::query(
+        r#"
+        SELECT f.forecast_date, f.program_code, f.final_date, f.passing_score, f.low, f.high, h.passing_score AS actual
+        FROM passing_score_forecasts f
+        LEFT JOIN history_stats h ON h.record_date = f.final_date AND h.program_code = f.program_code
+        ORDER BY f.program_code, f.final_date, f.forecast_date
+        "#,
+    )
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            let passing_score: Option<i32> = row.get("passing_score");
+            let low: Option<i32> = row.get("low");
+            let high: Option<i32> = row.get("high");
+            let actual: Option<i32> = row.get("actual");
+            ForecastAccuracy {
+                forecast_date: row.get("forecast_date"),
+                program_code: row.get("program_code"),
+                final_date: row.get("final_date"),
+                passing_score,
+                low,
+                high,
+                actual,
+                error: passing_score.zip(actual).map(|(p, a)| p - a),
+                in_range: low.zip(high).zip(actual).map(|((l, h), a)| (l..=h).contains(&a)),
+            }
+        })
+        .collect())
+}
+
+/// Отменяет фиксацию этапа: его зачисленные возвращаются в конкурс.
+pub async fn unfreeze_stage(pool: &SqlitePool, code: &str) -> Result<(), sqlx::Error> {
     let mut tx = pool.begin().await?;
 
-    sqlx::query("DELETE FROM applicants").execute(&mut *tx).await?;
-    sqlx::query("DELETE FROM history_stats").execute(&mut *tx).await?;
+    sqlx::query("DELETE FROM enrollments WHERE stage_code = ?")
+        .bind(code)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("UPDATE enrollment_stages SET frozen_at = NULL WHERE code = ?")
+        .bind(code)
+        .execute(&mut *tx)
+        .await?;
 
     tx.commit().await?;
     Ok(())
 }
+
+fn profile_from_row(row: sqlx::sqlite::SqliteRow) -> ImportProfile {
+    let columns_str: String = row.get("columns");
+    let agreed_true_str: String = row.get("agreed_true");
+    let agreed_false_str: String = row.get("agreed_false");
+
+    ImportProfile {
+        name: row.get("name"),
+        columns: serde_json::from_str(&columns_str).unwrap_or_default(),
+        agreed_true: serde_json::from_str(&agreed_true_str).unwrap_or_default(),
+        agreed_false: serde_json::from_str(&agreed_false_str).unwrap_or_default(),
+        priority_separator: row.get("priority_separator"),
+    }
+}
+
+pub async fn list_profiles(pool: &SqlitePool) -> Result<Vec<ImportProfile>, sqlx::Error> {
+    let rows = sqlx::query("SELECT name, columns, agreed_true, agreed_false, priority_separator FROM import_profiles ORDER BY name")
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows.into_iter().map(profile_from_row).collect())
+}
+
+pub async fn get_profile(pool: &SqlitePool, name: &str) -> Result<Option<ImportProfile>, sqlx::Error> {
+    let row = sqlx::query("SELECT name, columns, agreed_true, agreed_false, priority_separator FROM import_profiles WHERE name = ?")
+        .bind(name)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(row.map(profile_from_row))
+}
+
+pub async fn insert_profile(pool: &SqlitePool, profile: &ImportProfile) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO import_profiles (name, columns, agreed_true, agreed_false, priority_separator) VALUES (?, ?, ?, ?, ?)")
+        .bind(&profile.name)
+        .bind(serde_json::to_string(&profile.columns).unwrap_or_else(|_| "{}".to_string()))
+        .bind(serde_json::to_string(&profile.agreed_true).unwrap_or_else(|_| "[]".to_string()))
+        .bind(serde_json::to_string(&profile.agreed_false).unwrap_or_else(|_| "[]".to_string()))
+        .bind(&profile.priority_separator)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn update_profile(pool: &SqlitePool, profile: &ImportProfile) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("UPDATE import_profiles SET columns = ?, agreed_true = ?, agreed_false = ?, priority_separator = ? WHERE name = ?")
+        .bind(serde_json::to_string(&profile.columns).unwrap_or_else(|_| "{}".to_string()))
+        .bind(serde_json::to_string(&profile.agreed_true).unwrap_or_else(|_| "[]".to_string()))
+        .bind(serde_json::to_string(&profile.agreed_false).unwrap_or_else(|_| "[]".to_string()))
+        .bind(&profile.priority_separator)
+        .bind(&profile.name)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn delete_profile(pool: &SqlitePool, name: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM import_profiles WHERE name = ?")
+        .bind(name)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+fn snapshot_from_row(row: sqlx::sqlite::SqliteRow) -> Snapshot {
+    Snapshot {
+        id: row.get("id"),
+        report_date: row.get("report_date"),
+        seq: row.get("seq"),
+        created_at: row.get("created_at"),
+        applicants_count: row.get("applicants_count"),
+    }
+}
+
+// Только актуальные версии: по одной на дату, с наибольшим `seq`
+const SNAPSHOT_SELECT: &str = r#"
+    SELECT s.id, s.report_date, s.seq, s.created_at,
+           (SELECT COUNT(*) FROM applicants a WHERE a.snapshot_id = s.id) AS applicants_count
+    FROM snapshots s
+    WHERE s.seq = (SELECT MAX(v.seq) FROM snapshots v WHERE v.report_date = s.report_date)
+"#;
+
+/// Актуальные версии срезов в порядке дат.
+pub async fn list_snapshots(pool: &SqlitePool) -> Result<Vec<Snapshot>, sqlx::Error> {
+    let mut builder: QueryBuilder<Sqlite> = QueryBuilder::new(SNAPSHOT_SELECT);
+    builder.push(" ORDER BY s.report_date ASC");
+
+    let rows = builder.build().fetch_all(pool).await?;
+
+    Ok(rows.into_iter().map(snapshot_from_row).collect())
+}
+
+/// Актуальный срез — с самой поздней датой отчета.
+pub async fn latest_snapshot(pool: &SqlitePool) -> Result<Option<Snapshot>, sqlx::Error> {
+    let mut builder: QueryBuilder<Sqlite> = QueryBuilder::new(SNAPSHOT_SELECT);
+    builder.push(" ORDER BY s.report_date DESC LIMIT 1");
+
+    let row = builder.build().fetch_optional(pool).await?;
+
+    Ok(row.map(snapshot_from_row))
+}
+
+/// Актуальная версия среза за дату.
+pub async fn snapshot_by_date(pool: &SqlitePool, report_date: &str) -> Result<Option<Snapshot>, sqlx::Error> {
+    let mut builder: QueryBuilder<Sqlite> = QueryBuilder::new(SNAPSHOT_SELECT);
+    builder.push(" AND s.report_date = ");
+    builder.push_bind(report_date);
+
+    let row = builder.build().fetch_optional(pool).await?;
+
+    Ok(row.map(snapshot_from_row))
+}
+
+/// Срез, на который опирается импорт за `report_date`: за эту дату или последний до нее.
+pub async fn base_snapshot(pool: &SqlitePool, report_date: &str) -> Result<Option<Snapshot>, sqlx::Error> {
+    let mut builder: QueryBuilder<Sqlite> = QueryBuilder::new(SNAPSHOT_SELECT);
+    builder.push(" AND s.report_date <= ");
+    builder.push_bind(report_date);
+    builder.push(" ORDER BY s.report_date DESC LIMIT 1");
+
+    let row = builder.build().fetch_optional(pool).await?;
+
+    Ok(row.map(snapshot_from_row))
+}
+
+/// Срез за указанную дату, либо актуальный, если дата не передана.
+pub async fn resolve_snapshot(
+    pool: &SqlitePool,
+    report_date: Option<&str>,
+) -> Result<Option<Snapshot>, sqlx::Error> {
+    match report_date {
+        Some(date) if !date.is_empty() => snapshot_by_date(pool, date).await,
+        _ => latest_snapshot(pool).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    /// База в памяти живет, пока открыто соединение, поэтому соединение одно.
+    async fn test_pool() -> SqlitePool {
+        let pool = SqlitePoolOptions::new().max_connections(1).connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!("./migrations").run(&pool).await.unwrap();
+        pool
+    }
+
+    fn row(external_id: i32, agreed: bool) -> NewApplicant {
+        NewApplicant {
+            external_id,
+            full_name: format!("Абитуриент {}", external_id),
+            scores: BTreeMap::from([("math".to_string(), 70), ("rus".to_string(), 70)]),
+            score_achieve: 0,
+            agreed,
+            budget: true,
+            contract: false,
+            contract_agreed: false,
+            bvi: false,
+            priorities: vec!["ПМ".to_string()],
+            quota: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn same_day_merge_creates_next_version() {
+        let pool = test_pool().await;
+        let date = "2026-07-20";
+
+        let morning = import_batch(&pool, date, ImportMode::Replace, vec![row(1, false), row(2, false)], &[])
+            .await
+            .unwrap();
+        // Днем — только новые согласия
+        let merged = import_batch(&pool, date, ImportMode::Merge, vec![row(2, true)], &[]).await.unwrap();
+
+        let latest = latest_snapshot(&pool).await.unwrap().unwrap();
+        assert_eq!((latest.id, latest.seq), (merged, 2));
+        assert_eq!(snapshot_by_date(&pool, date).await.unwrap().unwrap().id, merged);
+        assert_eq!(list_snapshots(&pool).await.unwrap().len(), 1);
+
+        let agreed: Vec<(i32, bool)> = get_all_applicants(&pool, merged)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|a| (a.external_id, a.agreed))
+            .collect();
+        assert_eq!(agreed.len(), 2);
+        assert!(agreed.contains(&(1, false)) && agreed.contains(&(2, true)));
+
+        // Утренняя версия не изменилась
+        let before = get_all_applicants(&pool, morning).await.unwrap();
+        assert!(before.iter().all(|a| !a.agreed));
+    }
+}
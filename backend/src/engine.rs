@@ -0,0 +1,1072 @@
+//! Распределение абитуриентов по направлениям без обращения к БД: рейтинги,
+//! требования направлений и отложенное принятие по квотам и общему конкурсу.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet};
+use crate::models::{is_subject_code, Applicant, Enrollment, EnrollmentStage, FundingBasis, Program};
+
+fn get_program_limits(programs: &[Program]) -> HashMap<String, usize> {
+    programs
+        .iter()
+        .map(|p| (p.code.clone(), p.places.max(0) as usize))
+        .collect()
+}
+
+/// Зачисленный абитуриент: индекс в `applicants`, квота (`None` — общий конкурс)
+/// и основание (квоты и общий конкурс — бюджет).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Admission {
+    pub idx: usize,
+    pub quota: Option<String>,
+    pub basis: FundingBasis,
+}
+
+/// Зачисленные по направлениям: сначала по квотам, затем по общему конкурсу,
+/// затем на платные места.
+pub type Allocation = HashMap<String, Vec<Admission>>;
+
+/// Названия критериев рейтинга направления в порядке сравнения.
+pub fn rank_criteria_names(program: &Program) -> Vec<String> {
+    ["bvi", "score"]
+        .into_iter()
+        .map(String::from)
+        .chain(program.tie_break.iter().cloned())
+        .collect()
+}
+
+/// Значения критериев рейтинга (больше — выше): БВИ, конкурсный балл,
+/// затем правила `Program::tie_break` по порядку.
+pub fn rank_criteria(program: &Program, app: &Applicant) -> Vec<i32> {
+    let mut criteria = vec![app.bvi as i32, program_score(program, app)];
+    criteria.extend(program.tie_break.iter().map(|rule| tie_break_value(rule, program, app)));
+    criteria
+}
+
+fn tie_break_value(rule: &str, program: &Program, app: &Applicant) -> i32 {
+    match rule {
+        "achievements" => app.scores.achievements,
+        // Чем выше направление в списке приоритетов, тем больше значение
+        "priority" => priority_position(app, &program.code).map_or(i32::MIN, |i| -(i as i32)),
+        _ => rule
+            .strip_prefix("subject:")
+            .and_then(|subject| app.scores.get(subject))
+            .unwrap_or(0),
+    }
+}
+
+/// Допустимое правило для `Program::tie_break`.
+pub fn is_tie_break_rule(rule: &str) -> bool {
+    match rule.strip_prefix("subject:") {
+        Some(subject) => is_subject_code(subject),
+        None => rule == "achievements" || rule == "priority",
+    }
+}
+
+// Место в конкурсе направления: меньше — выше. Критерии по убыванию, при полном
+// равенстве — меньший `external_id`, затем порядок в исходном списке
+pub type RankKey = (Vec<Reverse<i32>>, i32, usize);
+
+pub fn rank_key(program: &Program, applicants: &[Applicant], idx: usize) -> RankKey {
+    let app = &applicants[idx];
+    (rank_criteria(program, app).into_iter().map(Reverse).collect(), app.external_id, idx)
+}
+
+/// Позиция направления в приоритетах абитуриента (первое вхождение).
+pub fn priority_position(app: &Applicant, code: &str) -> Option<usize> {
+    app.priorities.iter().position(|p| p == code)
+}
+
+/// Конкурс на направлении: общий или по одной из квот.
+struct Competition<'a> {
+    program: &'a Program,
+    capacity: usize,
+}
+
+/// Подано ли заявление на основание `basis` с согласием на зачисление по нему.
+pub fn competes_for(app: &Applicant, basis: FundingBasis) -> bool {
+    match basis {
+        FundingBasis::Budget => app.budget && app.agreed,
+        FundingBasis::Contract => app.contract && app.contract_agreed,
+    }
+}
+
+/// Допустимые направления абитуриента в порядке приоритета: без повторов
+/// и при пройденных минимальных баллах. Согласие проверяется по каждому основанию отдельно.
+fn acceptable_programs<'a>(app: &Applicant, by_code: &HashMap<&str, &'a Program>) -> Vec<&'a Program> {
+    let mut seen = HashSet::new();
+    app.priorities
+        .iter()
+        .filter(|code| seen.insert(code.as_str()))
+        .filter_map(|code| by_code.get(code.as_str()).copied())
+        .filter(|program| meets_requirements(program, app))
+        .collect()
+}
+
+/// Места абитуриентов в рейтингах направлений: `ranks[i][k]` — место абитуриента `i`
+/// на направлении `acceptable[i][k]` (меньше — выше). Ключ рейтинга считается один раз
+/// на пару направление-абитуриент, дальше конкурсы сравнивают только числа.
+fn rank_positions(
+    applicants: &[Applicant],
+    acceptable: &[Vec<&Program>],
+    program_index: &HashMap<&str, usize>,
+) -> Vec<Vec<u32>> {
+    let mut listed: Vec<Vec<(RankKey, usize)>> = vec![Vec::new(); program_index.len()];
+    for (idx, programs) in acceptable.iter().enumerate() {
+        for (k, program) in programs.iter().enumerate() {
+            listed[program_index[program.code.as_str()]].push((rank_key(program, applicants, idx), k));
+        }
+    }
+
+    let mut ranks: Vec<Vec<u32>> = acceptable.iter().map(|programs| vec![0; programs.len()]).collect();
+    for mut entries in listed {
+        // Последний элемент ключа — индекс абитуриента, ключи не повторяются
+        entries.sort_unstable();
+        for (position, ((_, _, idx), k)) in entries.into_iter().enumerate() {
+            ranks[idx][k] = position as u32;
+        }
+    }
+    ranks
+}
+
+/// Отложенное принятие (Гейл — Шепли), заявки подают абитуриенты.
+/// `choices[i]` — пары (конкурс — индекс в `competitions`, место абитуриента в рейтинге
+/// его направления) в порядке приоритета абитуриента `i`.
+///
+/// Абитуриент по очереди подает заявку в конкурсы своего списка. Конкурс держит
+/// лучших по рейтингу направления в пределах мест и вытесняет худшего, если пришел
+/// более сильный; вытесненный идет к следующему конкурсу в своем списке.
+/// Зачисленные в каждом конкурсе возвращаются в порядке рейтинга.
+fn deferred_acceptance(competitions: &[Competition], choices: &[Vec<(usize, u32)>]) -> Vec<Vec<usize>> {
+    let mut held: Vec<BinaryHeap<(u32, usize)>> = competitions.iter().map(|_| BinaryHeap::new()).collect();
+    let mut next_choice = vec![0usize; choices.len()];
+    let mut free: Vec<usize> = (0..choices.len()).filter(|&i| !choices[i].is_empty()).collect();
+
+    while let Some(idx) = free.pop() {
+        // Список исчерпан — абитуриент в этом конкурсе не зачислен
+        let Some(&(c, rank)) = choices[idx].get(next_choice[idx]) else {
+            continue;
+        };
+        next_choice[idx] += 1;
+
+        held[c].push((rank, idx));
+
+        // Мест не хватает — вытесняется худший из принятых (возможно, сам подавший)
+        if held[c].len() > competitions[c].capacity
+            && let Some((_, rejected)) = held[c].pop()
+        {
+            free.push(rejected);
+        }
+    }
+
+    held.into_iter()
+        .map(|list| list.into_sorted_vec().into_iter().map(|(_, idx)| idx).collect())
+        .collect()
+}
+
+/// Распределение без обращения к БД. Возвращает для каждого направления
+/// зачисленных: сначала по квотам, затем по общему конкурсу, затем по договору,
+/// каждый список — в порядке рейтинга.
+///
+/// 1. Бюджет: конкурсы по квотам и общие конкурсы направлений распределяются
+///    одним отложенным принятием. Список абитуриента — его направления по порядку,
+///    для каждого сначала место по своей квоте (если она там есть), затем общий
+///    конкурс. Общий конкурс — места направления минус занятые по квотам:
+///    незанятые места квот переходят в общий конкурс.
+/// 2. Договор: платные места направления; участвуют подавшие на договор
+///    с согласием, кто не прошел на бюджет.
+///
+/// Абитуриенты, отмеченные в `enrolled` (зачислены на прошлых этапах), не участвуют.
+/// На приоритетном этапе (`priority_only`) общий конкурс только для БВИ,
+/// платные места не распределяются.
+fn allocate_among(applicants: &[Applicant], programs: &[Program], enrolled: &[bool], priority_only: bool) -> Allocation {
+    let limits = get_program_limits(programs);
+    let by_code: HashMap<&str, &Program> = programs.iter().map(|p| (p.code.as_str(), p)).collect();
+    let acceptable: Vec<Vec<&Program>> = applicants.iter().map(|app| acceptable_programs(app, &by_code)).collect();
+    // Общие конкурсы и платные места идут в порядке `programs`
+    let program_index: HashMap<&str, usize> =
+        programs.iter().enumerate().map(|(i, p)| (p.code.as_str(), i)).collect();
+
+    let ranks = rank_positions(applicants, &acceptable, &program_index);
+
+    let mut result: Allocation =
+        programs.iter().map(|p| (p.code.clone(), Vec::new())).collect();
+
+    // Этап 1: бюджет. Сначала конкурсы по квотам, за ними общие конкурсы направлений
+    let mut competitions = Vec::new();
+    let mut quota_names: Vec<Option<&str>> = Vec::new();
+    let mut quota_index: HashMap<(&str, &str), usize> = HashMap::new();
+    for program in programs {
+        for (quota, &seats) in &program.quotas {
+            if seats > 0 {
+                quota_index.insert((program.code.as_str(), quota.as_str()), competitions.len());
+                quota_names.push(Some(quota.as_str()));
+                competitions.push(Competition { program, capacity: seats as usize });
+            }
+        }
+    }
+    let general_start = competitions.len();
+    for program in programs {
+        let reserved: usize = program.quotas.values().map(|&seats| seats.max(0) as usize).sum();
+        quota_names.push(None);
+        competitions.push(Competition { program, capacity: limits[&program.code].saturating_sub(reserved) });
+    }
+
+    let budget_choices: Vec<Vec<(usize, u32)>> = acceptable
+        .iter()
+        .enumerate()
+        .map(|(idx, programs)| {
+            let app = &applicants[idx];
+            if enrolled[idx] || !competes_for(app, FundingBasis::Budget) {
+                return Vec::new();
+            }
+            // БВИ поступают на общие места
+            let quota = app.quota.as_deref().filter(|_| !app.bvi);
+            let mut list = Vec::new();
+            for (p, &rank) in programs.iter().zip(&ranks[idx]) {
+                if let Some(quota) = quota
+                    && let Some(&c) = quota_index.get(&(p.code.as_str(), quota))
+                {
+                    list.push((c, rank));
+                }
+                if !priority_only || app.bvi {
+                    list.push((general_start + program_index[p.code.as_str()], rank));
+                }
+            }
+            list
+        })
+        .collect();
+
+    // Места квот освобождаются, когда их абитуриенты проходят в общий конкурс
+    // на более высокий приоритет. Освободившиеся места добавляются в общий конкурс,
+    // и распределение повторяется, пока места общего конкурса не перестанут расти
+    let budget_held = loop {
+        let held = deferred_acceptance(&competitions, &budget_choices);
+
+        let mut quota_filled = vec![0usize; programs.len()];
+        for (competition, admitted) in competitions[..general_start].iter().zip(&held) {
+            quota_filled[program_index[competition.program.code.as_str()]] += admitted.len();
+        }
+        let mut changed = false;
+        for (i, program) in programs.iter().enumerate() {
+            let capacity = limits[&program.code].saturating_sub(quota_filled[i]);
+            if competitions[general_start + i].capacity != capacity {
+                competitions[general_start + i].capacity = capacity;
+                changed = true;
+            }
+        }
+        if !changed {
+            break held;
+        }
+    };
+
+    // Зачислен на бюджет или на прошлом этапе — на платные места не претендует
+    let mut admitted = enrolled.to_vec();
+    for ((competition, quota), admitted_here) in competitions.iter().zip(&quota_names).zip(budget_held) {
+        let list = result.get_mut(&competition.program.code).unwrap();
+        for idx in admitted_here {
+            admitted[idx] = true;
+            list.push(Admission { idx, quota: quota.map(String::from), basis: FundingBasis::Budget });
+        }
+    }
+
+    // Этап 2: платные места
+    if priority_only {
+        return result;
+    }
+    let competitions: Vec<Competition> = programs
+        .iter()
+        .map(|program| Competition { program, capacity: program.contract_places.max(0) as usize })
+        .collect();
+    let contract_choices: Vec<Vec<(usize, u32)>> = acceptable
+        .iter()
+        .enumerate()
+        .map(|(idx, programs)| {
+            if admitted[idx] || !competes_for(&applicants[idx], FundingBasis::Contract) {
+                return Vec::new();
+            }
+            programs.iter().zip(&ranks[idx]).map(|(p, &rank)| (program_index[p.code.as_str()], rank)).collect()
+        })
+        .collect();
+
+    let contract_held = deferred_acceptance(&competitions, &contract_choices);
+    for (competition, admitted) in competitions.iter().zip(contract_held) {
+        let list = result.get_mut(&competition.program.code).unwrap();
+        list.extend(admitted.into_iter().map(|idx| Admission { idx, quota: None, basis: FundingBasis::Contract }));
+    }
+
+    result
+}
+
+/// Поэтапное зачисление на дату среза: зачисленные на закрытых этапах
+/// и правила текущего этапа.
+#[derive(Debug, Clone)]
+pub struct StagePlan {
+    // Зафиксированы на закрытых этапах: занимают места и в конкурсе не участвуют
+    pub enrolled: Vec<Enrollment>,
+    // Закрыт приоритетный этап: незанятые места квот ушли в общий конкурс
+    pub quotas_closed: bool,
+    pub priority_only: bool,
+    // Доля мест общего конкурса (в %), доступная к концу текущего этапа
+    pub places_percent: i32,
+}
+
+impl Default for StagePlan {
+    /// Без этапов: одно распределение на все места.
+    fn default() -> Self {
+        StagePlan { enrolled: Vec::new(), quotas_closed: false, priority_only: false, places_percent: 100 }
+    }
+}
+
+impl StagePlan {
+    /// План для среза за `date`. Закрыты этапы с зафиксированным зачислением и сроком
+    /// согласий раньше `date`; текущий — первый этап со сроком не раньше `date`,
+    /// после последнего срока распределяются все места. `stages` — по сроку согласий.
+    pub fn at(date: &str, stages: &[EnrollmentStage], enrollments: &[Enrollment]) -> Self {
+        let closed: Vec<&EnrollmentStage> = stages
+            .iter()
+            .filter(|s| s.frozen_at.is_some() && s.consent_deadline.as_str() < date)
+            .collect();
+        let current = stages.iter().find(|s| s.consent_deadline.as_str() >= date);
+
+        StagePlan {
+            enrolled: enrollments
+                .iter()
+                .filter(|e| closed.iter().any(|s| s.code == e.stage_code))
+                .cloned()
+                .collect(),
+            quotas_closed: closed.iter().any(|s| s.priority_only),
+            priority_only: current.is_some_and(|s| s.priority_only),
+            places_percent: current.map_or(100, |s| s.places_percent),
+        }
+    }
+}
+
+/// Места направлений, оставшиеся на текущем этапе: за вычетом зафиксированных
+/// зачислений и с ограничением общего конкурса долей этапа.
+fn remaining_programs(programs: &[Program], plan: &StagePlan) -> Vec<Program> {
+    programs
+        .iter()
+        .map(|program| {
+            let mut quota_taken: BTreeMap<&str, i32> = BTreeMap::new();
+            let (mut general_taken, mut contract_taken) = (0, 0);
+            for e in plan.enrolled.iter().filter(|e| e.program_code == program.code) {
+                match (&e.quota, e.basis) {
+                    (_, FundingBasis::Contract) => contract_taken += 1,
+                    (Some(quota), _) => *quota_taken.entry(quota.as_str()).or_insert(0) += 1,
+                    (None, _) => general_taken += 1,
+                }
+            }
+            let taken = |quota: &str| quota_taken.get(quota).copied().unwrap_or(0);
+
+            // После приоритетного этапа места квот — только занятые на нем
+            let (quotas, quota_reserved): (BTreeMap<String, i32>, i32) = if plan.quotas_closed {
+                (BTreeMap::new(), quota_taken.values().sum())
+            } else {
+                (
+                    program.quotas.iter().map(|(q, &seats)| (q.clone(), (seats - taken(q)).max(0))).collect(),
+                    program.quotas.iter().map(|(q, &seats)| seats.max(taken(q))).sum(),
+                )
+            };
+
+            let general_total = (program.places - quota_reserved).max(0);
+            // На приоритетном этапе общий конкурс только для БВИ — без ограничения долей
+            let general_open = if plan.priority_only {
+                general_total
+            } else {
+                (general_total * plan.places_percent.clamp(0, 100) + 99) / 100
+            };
+
+            Program {
+                places: quotas.values().sum::<i32>() + (general_open - general_taken).max(0),
+                contract_places: (program.contract_places - contract_taken).max(0),
+                quotas,
+                ..program.clone()
+            }
+        })
+        .collect()
+}
+
+/// Распределение с учетом этапов: зачисленные на закрытых этапах сохраняют
+/// свои места, остальные распределяются на оставшиеся места текущего этапа.
+/// Порядок списков — как у `allocate_among`.
+pub fn allocate_staged(applicants: &[Applicant], programs: &[Program], plan: &StagePlan) -> Allocation {
+    let position: HashMap<i32, usize> = applicants.iter().enumerate().map(|(i, a)| (a.external_id, i)).collect();
+    let mut enrolled = vec![false; applicants.len()];
+    for e in &plan.enrolled {
+        if let Some(&idx) = position.get(&e.external_id) {
+            enrolled[idx] = true;
+        }
+    }
+
+    let remaining = remaining_programs(programs, plan);
+    let mut result = allocate_among(applicants, &remaining, &enrolled, plan.priority_only);
+    if plan.enrolled.is_empty() {
+        return result;
+    }
+
+    for e in &plan.enrolled {
+        if let (Some(&idx), Some(list)) = (position.get(&e.external_id), result.get_mut(&e.program_code)) {
+            list.push(Admission { idx, quota: e.quota.clone(), basis: e.basis });
+        }
+    }
+    for program in programs {
+        if let Some(list) = result.get_mut(&program.code) {
+            list.sort_by_cached_key(|a| {
+                (a.basis == FundingBasis::Contract, a.quota.is_none(), a.quota.clone(), rank_key(program, applicants, a.idx))
+            });
+        }
+    }
+    result
+}
+
+/// Предметы одной позиции `Program::subjects`: `"phys|inf"` -> `phys`, `inf`.
+pub fn slot_subjects(slot: &str) -> impl Iterator<Item = &str> {
+    slot.split('|').map(str::trim).filter(|s| !s.is_empty())
+}
+
+/// Лучший балл позиции среди предметов, по которым пройден минимум направления.
+fn best_in_slot(program: &Program, app: &Applicant, slot: &str) -> Option<i32> {
+    slot_subjects(slot)
+        .filter_map(|subject| {
+            let score = app.scores.get(subject)?;
+            let min = program.min_scores.get(subject).copied().unwrap_or(0);
+            (score >= min).then_some(score)
+        })
+        .max()
+}
+
+/// Конкурсный балл на направление: сумма баллов по его предметам и за достижения.
+/// Из альтернатив (`phys|inf`) берется лучший подходящий предмет.
+pub fn program_score(program: &Program, app: &Applicant) -> i32 {
+    program
+        .subjects
+        .iter()
+        .filter_map(|slot| best_in_slot(program, app, slot))
+        .sum::<i32>()
+        + app.scores.achievements
+}
+
+/// Есть ли у абитуриента по каждой позиции направления предмет с баллом не ниже минимума.
+/// Зачисляемые без вступительных испытаний от минимальных баллов освобождены.
+pub fn meets_requirements(program: &Program, app: &Applicant) -> bool {
+    app.bvi
+        || program
+            .subjects
+            .iter()
+            .all(|slot| best_in_slot(program, app, slot).is_some())
+}
+
+/// Проставляет `current_program` по результату `allocate_staged`.
+pub fn apply_allocation(applicants: &mut [Applicant], allocation: &Allocation) {
+    for app in applicants.iter_mut() {
+        app.current_program = None;
+        app.admitted_quota = None;
+        app.admitted_basis = None;
+    }
+    for (code, admitted) in allocation {
+        for admission in admitted {
+            applicants[admission.idx].current_program = Some(code.clone());
+            applicants[admission.idx].admitted_quota = admission.quota.clone();
+            applicants[admission.idx].admitted_basis = Some(admission.basis);
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::models::{Scores, QUOTAS};
+    use crate::logic::{program_ranking, stage_enrollments};
+    use proptest::prelude::*;
+    use proptest::sample::subsequence;
+
+    const CODES: [&str; 4] = ["A", "B", "C", "D"];
+
+    /// Распределение без этапов.
+    pub(crate) fn allocate(applicants: &[Applicant], programs: &[Program]) -> Allocation {
+        allocate_staged(applicants, programs, &StagePlan::default())
+    }
+
+    // Наборы предметов, из которых выбираются направления
+    const LAYOUTS: [&[&str]; 3] = [&["math", "rus", "phys|inf"], &["math", "rus", "phys"], &["math", "rus", "inf"]];
+
+    const TIE_BREAKS: [&[&str]; 3] = [&["subject:math", "subject:rus"], &["achievements", "subject:inf"], &["priority"]];
+
+    /// `uniform` — у всех направлений одинаковый рейтинг и нет платных мест;
+    /// `with_quotas` — есть места по квотам.
+    fn program_strategy(code: &'static str, uniform: bool, with_quotas: bool) -> impl Strategy<Value = Program> {
+        (
+            (0..5i32, 0..3i32, 0..LAYOUTS.len(), 0..TIE_BREAKS.len()),
+            proptest::option::of(40..60i32),
+            [0..3i32, 0..3i32, 0..3i32],
+        )
+            .prop_map(move |((places, contract_places, layout, tie_break), min_math, seats)| {
+                let (layout, tie_break) = if uniform { (0, 0) } else { (layout, tie_break) };
+                let quotas: BTreeMap<String, i32> = if with_quotas {
+                    QUOTAS.iter().zip(seats).map(|(q, n)| (q.to_string(), n)).collect()
+                } else {
+                    BTreeMap::new()
+                };
+                Program {
+                    code: code.to_string(),
+                    name: code.to_string(),
+                    places: places.max(quotas.values().sum()),
+                    contract_places: if uniform { 0 } else { contract_places },
+                    subjects: LAYOUTS[layout].iter().map(|s| s.to_string()).collect(),
+                    min_scores: match min_math {
+                        Some(min) if !uniform => BTreeMap::from([("math".to_string(), min)]),
+                        _ => BTreeMap::new(),
+                    },
+                    quotas,
+                    tie_break: TIE_BREAKS[tie_break].iter().map(|s| s.to_string()).collect(),
+                }
+            },
+        )
+    }
+
+    fn programs_strategy(uniform: bool, with_quotas: bool) -> impl Strategy<Value = Vec<Program>> {
+        CODES.map(|code| program_strategy(code, uniform, with_quotas)).prop_map(Vec::from)
+    }
+
+    // Узкие диапазоны баллов, чтобы чаще встречались равенства
+    fn applicant_strategy(with_quotas: bool) -> impl Strategy<Value = Applicant> {
+        (
+            (any::<bool>(), proptest::bool::weighted(0.1)),
+            (proptest::bool::weighted(0.9), any::<bool>(), any::<bool>()),
+            (30..70i32, 30..70i32, proptest::option::of(30..70i32), proptest::option::of(30..70i32)),
+            0..3i32,
+            subsequence(CODES.to_vec(), 0..=CODES.len()).prop_shuffle(),
+            proptest::option::of(0..QUOTAS.len()),
+        )
+            .prop_map(move |((agreed, bvi), (budget, contract, contract_agreed), (math, rus, phys, inf), achievements, priorities, quota)| {
+                let mut subjects = BTreeMap::from([("math".to_string(), math), ("rus".to_string(), rus)]);
+                if let Some(v) = phys {
+                    subjects.insert("phys".to_string(), v);
+                }
+                if let Some(v) = inf {
+                    subjects.insert("inf".to_string(), v);
+                }
+                let scores = Scores { subjects, achievements };
+
+                Applicant {
+                    id: 0,
+                    external_id: 0,
+                    full_name: String::new(),
+                    agreed,
+                    budget,
+                    contract,
+                    contract_agreed,
+                    bvi,
+                    total_score: scores.total(),
+                    scores,
+                    current_program: None,
+                    priorities: priorities.into_iter().map(String::from).collect(),
+                    quota: quota.filter(|_| with_quotas).map(|q| QUOTAS[q].to_string()),
+                    admitted_quota: None,
+                    admitted_basis: None,
+                }
+            })
+    }
+
+    fn applicants_strategy(with_quotas: bool) -> impl Strategy<Value = Vec<Applicant>> {
+        proptest::collection::vec(applicant_strategy(with_quotas), 0..40).prop_map(|mut list| {
+            for (i, app) in list.iter_mut().enumerate() {
+                app.id = i as i32 + 1;
+                app.external_id = i as i32 + 1;
+            }
+            list
+        })
+    }
+
+    /// Куда, по какой квоте и на каком основании зачислен каждый абитуриент.
+    fn assignment(allocation: &Allocation, count: usize) -> Vec<Option<(String, Option<String>, FundingBasis)>> {
+        let mut result = vec![None; count];
+        for (code, admitted) in allocation {
+            for a in admitted {
+                result[a.idx] = Some((code.clone(), a.quota.clone(), a.basis));
+            }
+        }
+        result
+    }
+
+    /// Зачисленные на бюджет направления в конкурсе `quota` (`None` — общий).
+    fn competition(allocation: &Allocation, code: &str, quota: Option<&str>) -> Vec<usize> {
+        allocation[code]
+            .iter()
+            .filter(|a| a.basis == FundingBasis::Budget && a.quota.as_deref() == quota)
+            .map(|a| a.idx)
+            .collect()
+    }
+
+    /// Зачисленные на платные места направления.
+    fn contract_competition(allocation: &Allocation, code: &str) -> Vec<usize> {
+        allocation[code].iter().filter(|a| a.basis == FundingBasis::Contract).map(|a| a.idx).collect()
+    }
+
+    /// Позиция направления в приоритетах абитуриента (первое вхождение).
+    fn preference(app: &Applicant, code: &str) -> Option<usize> {
+        app.priorities.iter().position(|p| p == code)
+    }
+
+    // Эталон: последовательная диктатура по общему рейтингу (прежний жадный алгоритм)
+    fn serial_dictatorship(applicants: &[Applicant], programs: &[Program]) -> Allocation {
+        let reference = &programs[0];
+        let mut order: Vec<usize> =
+            (0..applicants.len()).filter(|&i| competes_for(&applicants[i], FundingBasis::Budget)).collect();
+        order.sort_by_key(|&i| rank_key(reference, applicants, i));
+
+        let mut lists: Allocation = programs.iter().map(|p| (p.code.clone(), Vec::new())).collect();
+        for idx in order {
+            for code in &applicants[idx].priorities {
+                let Some(program) = programs.iter().find(|p| &p.code == code) else {
+                    continue;
+                };
+                let list = lists.get_mut(code).unwrap();
+                if meets_requirements(program, &applicants[idx]) && list.len() < program.places as usize {
+                    list.push(Admission { idx, quota: None, basis: FundingBasis::Budget });
+                    break;
+                }
+            }
+        }
+        lists
+    }
+
+    /// Проверка устойчивости одного конкурса: каждый, кто хотел бы сюда больше,
+    /// чем туда, куда попал в этом этапе, уступает всем зачисленным, а мест нет.
+    fn check_no_envy(
+        applicants: &[Applicant],
+        program: &Program,
+        admitted: &[usize],
+        capacity: usize,
+        idx: usize,
+        current: Option<usize>,
+    ) -> Result<(), TestCaseError> {
+        let app = &applicants[idx];
+        let Some(pref) = preference(app, &program.code) else {
+            return Ok(());
+        };
+        if current.is_some_and(|c| c <= pref) || !meets_requirements(program, app) {
+            return Ok(());
+        }
+
+        let key = rank_key(program, applicants, idx);
+        prop_assert_eq!(admitted.len(), capacity);
+        prop_assert!(
+            admitted.iter().all(|&other| rank_key(program, applicants, other) < key),
+            "абитуриент {} обоснованно претендует на {}", idx, program.code
+        );
+        Ok(())
+    }
+
+    proptest! {
+        #[test]
+        fn respects_capacity_and_eligibility(
+            programs in programs_strategy(false, true),
+            applicants in applicants_strategy(true),
+        ) {
+            let allocation = allocate(&applicants, &programs);
+            let mut seen = HashSet::new();
+
+            for program in &programs {
+                let admitted = &allocation[&program.code];
+                let contract = contract_competition(&allocation, &program.code);
+                prop_assert!(admitted.len() - contract.len() <= program.places as usize);
+                prop_assert!(contract.len() <= program.contract_places as usize);
+
+                for a in admitted {
+                    let app = &applicants[a.idx];
+                    prop_assert!(seen.insert(a.idx), "абитуриент {} зачислен дважды", a.idx);
+                    prop_assert!(competes_for(app, a.basis));
+                    prop_assert!(app.priorities.contains(&program.code));
+                    prop_assert!(meets_requirements(program, app));
+                    if a.quota.is_some() {
+                        prop_assert_eq!(&a.quota, &app.quota);
+                        prop_assert_eq!(a.basis, FundingBasis::Budget);
+                        prop_assert!(!app.bvi);
+                    }
+                }
+
+                let mut quota_filled = 0;
+                for (quota, &seats) in &program.quotas {
+                    let list = competition(&allocation, &program.code, Some(quota));
+                    prop_assert!(list.len() <= seats as usize);
+                    quota_filled += list.len();
+                }
+
+                // Списки упорядочены по рейтингу направления
+                let general = competition(&allocation, &program.code, None);
+                prop_assert!(general.len() <= program.places as usize - quota_filled);
+                for list in [general, contract] {
+                    let keys: Vec<RankKey> = list.iter().map(|&i| rank_key(program, &applicants, i)).collect();
+                    prop_assert!(keys.windows(2).all(|w| w[0] < w[1]));
+                }
+            }
+        }
+
+        #[test]
+        fn is_stable(
+            programs in programs_strategy(false, true),
+            applicants in applicants_strategy(true),
+        ) {
+            let allocation = allocate(&applicants, &programs);
+            let assigned = assignment(&allocation, applicants.len());
+
+            for (idx, app) in applicants.iter().enumerate() {
+                // Договор: не прошедший на бюджет уступает всем зачисленным на платные места
+                if competes_for(app, FundingBasis::Contract)
+                    && !matches!(&assigned[idx], Some((_, _, FundingBasis::Budget)))
+                {
+                    let current = assigned[idx].as_ref().and_then(|(code, _, _)| preference(app, code));
+                    for program in &programs {
+                        let admitted = contract_competition(&allocation, &program.code);
+                        check_no_envy(&applicants, program, &admitted, program.contract_places as usize, idx, current)?;
+                    }
+                }
+
+                if !competes_for(app, FundingBasis::Budget) {
+                    continue;
+                }
+
+                // Место на бюджете: позиция направления и прошел ли по квоте
+                let (current, by_quota) = match &assigned[idx] {
+                    Some((code, quota, FundingBasis::Budget)) => (preference(app, code), quota.is_some()),
+                    _ => (None, false),
+                };
+
+                // Квота стоит в списке раньше общего конкурса того же направления:
+                // прошедший на направление по общему конкурсу претендовал и на его квоту
+                if let Some(quota) = app.quota.as_ref().filter(|_| !app.bvi) {
+                    let quota_current = current.map(|c| if by_quota { c } else { c + 1 });
+                    for program in programs.iter().filter(|p| p.quotas.get(quota).is_some_and(|&n| n > 0)) {
+                        let admitted = competition(&allocation, &program.code, Some(quota));
+                        check_no_envy(&applicants, program, &admitted, program.quotas[quota] as usize, idx, quota_current)?;
+                    }
+                }
+
+                // Общий конкурс: все места, включая вернувшиеся из квот, заняты более сильными.
+                // Это касается и прошедших по квоте — на более желанные направления
+                for program in &programs {
+                    let quota_filled = allocation[&program.code].iter().filter(|a| a.quota.is_some()).count();
+                    let admitted = competition(&allocation, &program.code, None);
+                    check_no_envy(&applicants, program, &admitted, program.places as usize - quota_filled, idx, current)?;
+                }
+            }
+        }
+
+        #[test]
+        fn matches_serial_dictatorship_for_uniform_rankings(
+            programs in programs_strategy(true, false),
+            applicants in applicants_strategy(false),
+        ) {
+            let allocation = allocate(&applicants, &programs);
+            let expected = serial_dictatorship(&applicants, &programs);
+
+            prop_assert_eq!(
+                assignment(&allocation, applicants.len()),
+                assignment(&expected, applicants.len())
+            );
+        }
+
+        #[test]
+        fn ranking_matches_allocation_order(
+            programs in programs_strategy(false, true),
+            applicants in applicants_strategy(true),
+        ) {
+            let allocation = allocate(&applicants, &programs);
+
+            for program in &programs {
+                // Зачисленные по общему конкурсу идут в конкурсном списке в том же порядке
+                let general: Vec<i32> = competition(&allocation, &program.code, None)
+                    .into_iter()
+                    .map(|i| applicants[i].external_id)
+                    .collect();
+                let ranking = program_ranking(program, &applicants);
+                let listed: Vec<i32> = ranking
+                    .entries
+                    .iter()
+                    .filter(|e| general.contains(&e.external_id))
+                    .map(|e| e.external_id)
+                    .collect();
+                prop_assert_eq!(listed, general);
+                prop_assert_eq!(ranking.criteria.len(), 2 + program.tie_break.len());
+            }
+        }
+
+        #[test]
+        fn ignores_program_catalog_order(
+            programs in programs_strategy(false, true),
+            applicants in applicants_strategy(true),
+        ) {
+            // Результат не зависит от порядка направлений в справочнике
+            let mut reversed = programs.clone();
+            reversed.reverse();
+
+            prop_assert_eq!(
+                assignment(&allocate(&applicants, &programs), applicants.len()),
+                assignment(&allocate(&applicants, &reversed), applicants.len())
+            );
+        }
+
+        #[test]
+        fn ignores_applicant_order(
+            programs in programs_strategy(false, true),
+            (applicants, shuffled) in applicants_strategy(true)
+                .prop_flat_map(|list| (Just(list.clone()), Just(list).prop_shuffle())),
+        ) {
+            // Заявки подаются в порядке списка абитуриентов, но итог от него не зависит
+            let by_external_id = |list: &[Applicant]| {
+                let placed = assignment(&allocate(list, &programs), list.len());
+                let mut rows: Vec<_> = list.iter().map(|a| a.external_id).zip(placed).collect();
+                rows.sort_by_key(|(id, _)| *id);
+                rows
+            };
+
+            prop_assert_eq!(by_external_id(&applicants), by_external_id(&shuffled));
+        }
+    }
+
+    pub(crate) fn program(code: &str, places: i32, quotas: &[(&str, i32)]) -> Program {
+        Program {
+            code: code.to_string(),
+            name: code.to_string(),
+            places,
+            contract_places: 0,
+            subjects: vec!["math".to_string()],
+            min_scores: BTreeMap::new(),
+            quotas: quotas.iter().map(|(q, n)| (q.to_string(), *n)).collect(),
+            tie_break: vec!["subject:math".to_string()],
+        }
+    }
+
+    pub(crate) fn applicant(math: i32, priorities: &[&str], quota: Option<&str>) -> Applicant {
+        Applicant {
+            id: 0,
+            external_id: 0,
+            full_name: String::new(),
+            agreed: true,
+            budget: true,
+            contract: false,
+            contract_agreed: false,
+            bvi: false,
+            total_score: math,
+            scores: Scores { subjects: BTreeMap::from([("math".to_string(), math)]), achievements: 0 },
+            current_program: None,
+            priorities: priorities.iter().map(|s| s.to_string()).collect(),
+            quota: quota.map(String::from),
+            admitted_quota: None,
+            admitted_basis: None,
+        }
+    }
+
+    /// Нумерует абитуриентов по порядку: `external_id` — индекс в списке.
+    pub(crate) fn numbered(mut applicants: Vec<Applicant>) -> Vec<Applicant> {
+        for (i, app) in applicants.iter_mut().enumerate() {
+            app.external_id = i as i32;
+        }
+        applicants
+    }
+
+    /// Абитуриенты с проставленным зачислением по распределению без этапов.
+    pub(crate) fn allocated(mut applicants: Vec<Applicant>, programs: &[Program]) -> Vec<Applicant> {
+        let allocation = allocate(&applicants, programs);
+        apply_allocation(&mut applicants, &allocation);
+        applicants
+    }
+
+    pub(crate) fn indices(list: &[Admission]) -> Vec<usize> {
+        list.iter().map(|a| a.idx).collect()
+    }
+
+    /// Запуск: `cargo test --release -- --ignored`
+    #[test]
+    #[ignore]
+    fn allocates_100k_applicants_well_under_a_second() {
+        // Детерминированный генератор, чтобы замер не зависел от запуска
+        let mut state = 42u64;
+        let mut next = move |n: u64| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (state >> 33) % n
+        };
+
+        let programs: Vec<Program> = (0..40)
+            .map(|i| Program {
+                contract_places: 100,
+                subjects: vec!["math".to_string(), "rus".to_string()],
+                tie_break: vec!["subject:math".to_string(), "priority".to_string()],
+                ..program(&format!("P{}", i), 500, &[("special", 25), ("target", 50)])
+            })
+            .collect();
+        let applicants: Vec<Applicant> = (0..100_000)
+            .map(|_| {
+                let quota = match next(10) {
+                    0 => Some("special"),
+                    1 => Some("target"),
+                    _ => None,
+                };
+                let mut app = applicant(next(101) as i32, &[], quota);
+                app.scores.subjects.insert("rus".to_string(), next(101) as i32);
+                app.priorities = (0..5).map(|_| format!("P{}", next(40))).collect();
+                app.agreed = next(4) > 0;
+                app.contract = next(2) == 0;
+                app.contract_agreed = app.contract;
+                app
+            })
+            .collect();
+        let applicants = numbered(applicants);
+
+        let started = std::time::Instant::now();
+        let allocation = allocate(&applicants, &programs);
+        let elapsed = started.elapsed();
+
+        let admitted: usize = allocation.values().map(Vec::len).sum();
+        assert!(admitted > 20_000);
+        assert!(elapsed.as_millis() < 500, "распределение заняло {:?}", elapsed);
+    }
+
+    #[test]
+    fn displaces_weaker_applicant_to_next_priority() {
+        let programs = vec![program("A", 1, &[]), program("B", 1, &[])];
+        // Слабый подает первым, сильный вытесняет его с "A"
+        let applicants = vec![applicant(50, &["A", "B"], None), applicant(90, &["A"], None)];
+
+        let allocation = allocate(&applicants, &programs);
+        assert_eq!(indices(&allocation["A"]), vec![1]);
+        assert_eq!(indices(&allocation["B"]), vec![0]);
+    }
+
+    #[test]
+    fn fills_quota_first_and_returns_unused_seats() {
+        // 3 места, из них 1 особая квота и 1 целевая; целевых заявлений нет
+        let programs = vec![program("A", 3, &[("special", 1), ("target", 1)])];
+        let applicants = vec![
+            applicant(90, &["A"], None),
+            applicant(80, &["A"], None),
+            applicant(70, &["A"], None),
+            applicant(40, &["A"], Some("special")),
+        ];
+
+        let allocation = allocate(&applicants, &programs);
+        assert_eq!(
+            allocation["A"],
+            vec![
+                Admission { idx: 3, quota: Some("special".to_string()), basis: FundingBasis::Budget },
+                Admission { idx: 0, quota: None, basis: FundingBasis::Budget },
+                Admission { idx: 1, quota: None, basis: FundingBasis::Budget },
+            ]
+        );
+    }
+
+    #[test]
+    fn quota_applicant_competes_in_general_for_higher_priority() {
+        // Целевая квота есть только на "B"; на "A" абитуриент проходит по общему конкурсу
+        let programs = vec![program("A", 1, &[]), program("B", 2, &[("target", 1)])];
+        let applicants = vec![
+            applicant(90, &["A", "B"], Some("target")),
+            applicant(80, &["A"], None),
+            applicant(50, &["B"], Some("target")),
+            applicant(60, &["B"], None),
+        ];
+
+        let allocation = allocate(&applicants, &programs);
+        assert_eq!(
+            allocation["A"],
+            vec![Admission { idx: 0, quota: None, basis: FundingBasis::Budget }]
+        );
+        // Место целевой квоты на "B" достается следующему по квоте
+        assert_eq!(
+            allocation["B"],
+            vec![
+                Admission { idx: 2, quota: Some("target".to_string()), basis: FundingBasis::Budget },
+                Admission { idx: 3, quota: None, basis: FundingBasis::Budget },
+            ]
+        );
+    }
+
+    #[test]
+    fn bvi_goes_first_and_takes_general_seat() {
+        let programs = vec![program("A", 2, &[("special", 1)])];
+        let mut olympiad = applicant(0, &["A"], Some("special"));
+        olympiad.bvi = true;
+        let applicants = vec![applicant(90, &["A"], None), applicant(80, &["A"], None), olympiad];
+
+        let allocation = allocate(&applicants, &programs);
+        // Квота не занята и вернулась в общий конкурс; БВИ — первым, несмотря на 0 баллов
+        assert_eq!(
+            allocation["A"],
+            vec![
+                Admission { idx: 2, quota: None, basis: FundingBasis::Budget },
+                Admission { idx: 0, quota: None, basis: FundingBasis::Budget },
+            ]
+        );
+    }
+
+    #[test]
+    fn contract_runs_after_budget() {
+        let mut programs = vec![program("A", 1, &[]), program("B", 1, &[])];
+        programs[0].contract_places = 1;
+        let mut applicants = vec![
+            applicant(90, &["A", "B"], None),
+            applicant(80, &["A", "B"], None),
+            applicant(70, &["A"], None),
+            applicant(60, &["A"], None),
+        ];
+        // Все подали и на договор; у 60 нет согласия на бюджет, у 70 — на договор
+        for app in &mut applicants {
+            app.contract = true;
+            app.contract_agreed = true;
+        }
+        applicants[2].contract_agreed = false;
+        applicants[3].agreed = false;
+
+        let allocation = allocate(&applicants, &programs);
+        // 80 проходит на бюджет "B" и в конкурсе на договор "A" не участвует
+        assert_eq!(allocation["A"], vec![
+            Admission { idx: 0, quota: None, basis: FundingBasis::Budget },
+            Admission { idx: 3, quota: None, basis: FundingBasis::Contract },
+        ]);
+        assert_eq!(allocation["B"], vec![Admission { idx: 1, quota: None, basis: FundingBasis::Budget }]);
+    }
+
+    #[test]
+    fn stages_keep_frozen_and_open_places_by_share() {
+        // 10 мест, из них 2 по особой квоте
+        let programs = vec![program("A", 10, &[("special", 2)])];
+        let mut applicants = vec![applicant(40, &["A"], Some("special")), applicant(0, &["A"], None)];
+        applicants[1].bvi = true;
+        applicants.extend((0..6).map(|i| applicant(90 - i * 10, &["A"], None)));
+        let mut applicants = numbered(applicants);
+
+        let stage = |code: &str, deadline: &str, percent: i32, priority_only: bool| EnrollmentStage {
+            code: code.to_string(),
+            name: code.to_string(),
+            consent_deadline: deadline.to_string(),
+            places_percent: percent,
+            priority_only,
+            frozen_at: None,
+            enrolled: 0,
+        };
+        let mut stages = vec![stage("priority", "2026-07-28", 100, true), stage("first", "2026-08-02", 50, false)];
+
+        // Приоритетный этап: только квота и БВИ
+        let plan = StagePlan::at("2026-07-27", &stages, &[]);
+        let allocation = allocate_staged(&applicants, &programs, &plan);
+        assert_eq!(indices(&allocation["A"]), vec![0, 1]);
+
+        let mut current = applicants.clone();
+        apply_allocation(&mut current, &allocation);
+        let enrollments = stage_enrollments("priority", &current);
+        stages[0].frozen_at = Some("2026-07-27".to_string());
+
+        // Первая волна: квоты закрыты, общий конкурс — 9 мест, к концу волны заполнена половина (5),
+        // одно из них уже у БВИ
+        let plan = StagePlan::at("2026-07-30", &stages, &enrollments);
+        assert!(plan.quotas_closed && !plan.priority_only);
+        let allocation = allocate_staged(&applicants, &programs, &plan);
+        assert_eq!(indices(&allocation["A"]), vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(allocation["A"][0].quota.as_deref(), Some("special"));
+
+        // Зачисленный на прошлом этапе сохраняет место, даже если забрал согласие
+        applicants[0].agreed = false;
+        assert_eq!(allocate_staged(&applicants, &programs, &plan), allocation);
+    }
+}
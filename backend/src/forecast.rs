@@ -0,0 +1,444 @@
+//! Прогнозы к окончанию приема согласий: вероятность зачисления абитуриентов
+//! по методу Монте-Карло и проходной балл по тренду истории.
+
+use chrono::NaiveDate;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::thread;
+use crate::engine::{allocate_staged, StagePlan};
+use crate::models::{Applicant, ApplicantForecast, ChoiceForecast, FundingBasis, HistoryPoint, PassingScoreForecast, Program};
+
+/// Генератор SplitMix64: прогоны воспроизводимы по `seed`.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Равномерно в `[0, 1)`.
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Дней от `from` до `to` (даты `YYYY-MM-DD`).
+pub fn days_between(from: &str, to: &str) -> Option<i64> {
+    let parse = |date: &str| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok();
+    Some((parse(to)? - parse(from)?).num_days())
+}
+
+// Меньше абитуриентов в группе направления — доля по ней слишком шумная,
+// для группы берется общая вероятность
+const MIN_CONSENT_GROUP: usize = 10;
+
+/// Вероятности, что абитуриент без согласия на бюджет подаст его к `final_date`:
+/// общая и по направлению первого приоритета.
+pub struct ConsentRates {
+    pub overall: f64,
+    pub by_program: BTreeMap<String, f64>,
+}
+
+impl ConsentRates {
+    /// Вероятность для абитуриента — по группе направления его первого приоритета.
+    pub fn for_applicant(&self, app: &Applicant) -> f64 {
+        app.priorities
+            .first()
+            .and_then(|code| self.by_program.get(code))
+            .copied()
+            .unwrap_or(self.overall)
+    }
+}
+
+/// Оценивает вероятность подачи согласия к `final_date`.
+///
+/// По предыдущему срезу `earlier` считается доля подавших согласие среди тех,
+/// у кого его не было, и переводится в дневную интенсивность, которая продлевается
+/// на оставшиеся до `final_date` дни. Без предыдущего среза — доля подавших согласие
+/// в текущем срезе. Доли считаются по всем абитуриентам и отдельно по группам
+/// направления первого приоритета (если в группе не меньше `MIN_CONSENT_GROUP` человек).
+pub fn consent_rates(
+    earlier: Option<(&str, &[Applicant])>,
+    current_date: &str,
+    current: &[Applicant],
+    final_date: &str,
+) -> ConsentRates {
+    let remaining = days_between(current_date, final_date).unwrap_or(0);
+    if remaining <= 0 {
+        return ConsentRates { overall: 0.0, by_program: BTreeMap::new() };
+    }
+
+    // Первый приоритет и подано ли согласие к текущему срезу; `days` — за сколько дней
+    let observed = earlier.and_then(|(date, before)| {
+        let days = days_between(date, current_date).filter(|&d| d > 0)?;
+        let now: HashMap<i32, bool> = current.iter().map(|a| (a.external_id, a.agreed)).collect();
+        let pending: Vec<(Option<&str>, bool)> = before
+            .iter()
+            .filter(|a| a.budget && !a.agreed)
+            .filter_map(|a| Some((a.priorities.first().map(String::as_str), *now.get(&a.external_id)?)))
+            .collect();
+        (!pending.is_empty()).then_some((pending, days))
+    });
+
+    // Без истории — текущая доля согласий, она не продлевается
+    let (outcomes, extend): (Vec<(Option<&str>, bool)>, Option<i64>) = match observed {
+        Some((pending, days)) => (pending, Some(days)),
+        None => (
+            current
+                .iter()
+                .filter(|a| a.budget)
+                .map(|a| (a.priorities.first().map(String::as_str), a.agreed))
+                .collect(),
+            None,
+        ),
+    };
+    let rate = |group: &[bool]| -> f64 {
+        if group.is_empty() {
+            return 0.0;
+        }
+        let share = group.iter().filter(|&&agreed| agreed).count() as f64 / group.len() as f64;
+        match extend {
+            Some(days) => {
+                let daily = 1.0 - (1.0 - share).powf(1.0 / days as f64);
+                1.0 - (1.0 - daily).powf(remaining as f64)
+            }
+            None => share,
+        }
+    };
+
+    let mut groups: BTreeMap<&str, Vec<bool>> = BTreeMap::new();
+    for &(code, agreed) in &outcomes {
+        if let Some(code) = code {
+            groups.entry(code).or_default().push(agreed);
+        }
+    }
+    let all: Vec<bool> = outcomes.iter().map(|&(_, agreed)| agreed).collect();
+
+    ConsentRates {
+        overall: rate(&all),
+        by_program: groups
+            .into_iter()
+            .filter(|(_, group)| group.len() >= MIN_CONSENT_GROUP)
+            .map(|(code, group)| (code.to_string(), rate(&group)))
+            .collect(),
+    }
+}
+
+/// Число прогонов, в которых абитуриент зачислен на направление (индекс в `programs`):
+/// `[всего, на бюджет]`.
+type Counts = Vec<HashMap<usize, [u32; 2]>>;
+
+/// Прогноз зачисления: `runs` прогонов распределения, в каждом абитуриент `i` без согласия
+/// (на бюджет и по договору) подает его с вероятностью `probabilities[i]`. Поданные согласия
+/// не отзываются, зачисленные на закрытых этапах (`plan`) сохраняют места.
+/// Прогоны делятся между потоками; результат зависит только от `seed`.
+pub fn admission_forecast(
+    applicants: &[Applicant],
+    programs: &[Program],
+    plan: &StagePlan,
+    probabilities: &[f64],
+    runs: u32,
+    seed: u64,
+) -> Vec<ApplicantForecast> {
+    let program_index: HashMap<&str, usize> =
+        programs.iter().enumerate().map(|(i, p)| (p.code.as_str(), i)).collect();
+    let threads = thread::available_parallelism().map_or(1, |n| n.get()).min(runs.max(1) as usize);
+
+    let run_share = |first: usize| -> Counts {
+        let mut counts: Counts = vec![HashMap::new(); applicants.len()];
+        let mut sampled = applicants.to_vec();
+        for run in (first..runs as usize).step_by(threads) {
+            let mut rng = SplitMix64(seed.wrapping_add(run as u64));
+            for ((app, sample), &probability) in applicants.iter().zip(sampled.iter_mut()).zip(probabilities) {
+                sample.agreed = app.agreed || (app.budget && rng.next_f64() < probability);
+                sample.contract_agreed = app.contract_agreed || (app.contract && rng.next_f64() < probability);
+            }
+
+            for (code, admitted) in allocate_staged(&sampled, programs, plan) {
+                let program = program_index[code.as_str()];
+                for admission in admitted {
+                    let count = counts[admission.idx].entry(program).or_default();
+                    count[0] += 1;
+                    if admission.basis == FundingBasis::Budget {
+                        count[1] += 1;
+                    }
+                }
+            }
+        }
+        counts
+    };
+
+    let mut counts: Counts = vec![HashMap::new(); applicants.len()];
+    thread::scope(|scope| {
+        let handles: Vec<_> = (0..threads).map(|first| scope.spawn(move || run_share(first))).collect();
+        for handle in handles {
+            let share = handle.join().expect("поток прогноза завершился с ошибкой");
+            for (total, part) in counts.iter_mut().zip(share) {
+                for (program, [admitted, budget]) in part {
+                    let count = total.entry(program).or_default();
+                    count[0] += admitted;
+                    count[1] += budget;
+                }
+            }
+        }
+    });
+
+    let share = |n: u32| if runs == 0 { 0.0 } else { n as f64 / runs as f64 };
+    applicants
+        .iter()
+        .zip(&counts)
+        .map(|(app, counts)| {
+            let mut seen = HashSet::new();
+            let choices: Vec<ChoiceForecast> = app
+                .priorities
+                .iter()
+                .enumerate()
+                .filter(|(_, code)| seen.insert(code.as_str()))
+                .map(|(i, code)| {
+                    let [admitted, budget] = program_index
+                        .get(code.as_str())
+                        .and_then(|p| counts.get(p))
+                        .copied()
+                        .unwrap_or_default();
+                    ChoiceForecast {
+                        priority: i as i32 + 1,
+                        program_code: code.clone(),
+                        probability: share(admitted),
+                        budget_probability: share(budget),
+                    }
+                })
+                .collect();
+
+            ApplicantForecast {
+                external_id: app.external_id,
+                full_name: app.full_name.clone(),
+                agreed: app.agreed,
+                admitted_probability: share(counts.values().map(|c| c[0]).sum()),
+                choices,
+            }
+        })
+        .collect()
+}
+
+/// Прямая по методу наименьших квадратов.
+struct Trend {
+    intercept: f64,
+    slope: f64,
+    n: usize,
+    mean_x: f64,
+    sxx: f64,
+    // Стандартная ошибка остатков; считается от 3 точек
+    residual: Option<f64>,
+}
+
+impl Trend {
+    fn fit(points: &[(f64, f64)]) -> Option<Self> {
+        if points.is_empty() {
+            return None;
+        }
+        let n = points.len();
+        let mean_x = points.iter().map(|p| p.0).sum::<f64>() / n as f64;
+        let mean_y = points.iter().map(|p| p.1).sum::<f64>() / n as f64;
+        let sxx: f64 = points.iter().map(|p| (p.0 - mean_x).powi(2)).sum();
+        let sxy: f64 = points.iter().map(|p| (p.0 - mean_x) * (p.1 - mean_y)).sum();
+
+        let slope = if sxx > 0.0 { sxy / sxx } else { 0.0 };
+        let intercept = mean_y - slope * mean_x;
+        let residual = (n >= 3).then(|| {
+            let sse: f64 = points.iter().map(|p| (p.1 - intercept - slope * p.0).powi(2)).sum();
+            (sse / (n - 2) as f64).sqrt()
+        });
+
+        Some(Trend { intercept, slope, n, mean_x, sxx, residual })
+    }
+
+    fn at(&self, x: f64) -> f64 {
+        self.intercept + self.slope * x
+    }
+
+    /// Полуширина 95% интервала прогноза в точке `x`.
+    fn margin(&self, x: f64) -> Option<f64> {
+        let residual = self.residual?;
+        let leverage = if self.sxx > 0.0 { (x - self.mean_x).powi(2) / self.sxx } else { 0.0 };
+        Some(t_quantile_95(self.n - 2) * residual * (1.0 + 1.0 / self.n as f64 + leverage).sqrt())
+    }
+}
+
+/// Двусторонний 95% квантиль распределения Стьюдента: таблица для малых степеней
+/// свободы, дальше — разложение Корниша — Фишера.
+fn t_quantile_95(df: usize) -> f64 {
+    match df {
+        1 => 12.706,
+        2 => 4.303,
+        _ => {
+            let z: f64 = 1.96;
+            let d = df as f64;
+            z + (z.powi(3) + z) / (4.0 * d) + (5.0 * z.powi(5) + 16.0 * z.powi(3) + 3.0 * z) / (96.0 * d * d)
+        }
+    }
+}
+
+/// Прогноз проходного балла направления на `final_date` по истории `history` (по датам).
+///
+/// Тренд строится по дням, когда бюджетные места были заполнены: при недоборе
+/// проходной балл — просто минимальный из зачисленных. Если таких дней нет,
+/// берутся все дни с зачисленными. Согласия продлеваются отдельным трендом.
+pub fn passing_score_forecast(program: &Program, history: &[HistoryPoint], final_date: &str) -> Option<PassingScoreForecast> {
+    let first = history.first()?;
+    let last = history.last()?;
+    let day = |date: &str| days_between(&first.date, date).unwrap_or(0) as f64;
+
+    let full: Vec<&HistoryPoint> = history
+        .iter()
+        .filter(|h| program.places > 0 && h.places_filled >= program.places)
+        .collect();
+    let scored: Vec<&HistoryPoint> = if full.is_empty() {
+        history.iter().filter(|h| h.places_filled > 0).collect()
+    } else {
+        full
+    };
+    let points: Vec<(f64, f64)> = scored.iter().map(|h| (day(&h.date), h.passing_score as f64)).collect();
+
+    let target = day(final_date);
+    let trend = Trend::fit(&points);
+    let passing_score = trend.as_ref().map(|t| t.at(target).round().max(0.0) as i32);
+    let margin = trend.as_ref().and_then(|t| t.margin(target));
+
+    let consents: Vec<(f64, f64)> = history.iter().map(|h| (day(&h.date), h.consents as f64)).collect();
+    let projected_consents = Trend::fit(&consents).map(|t| t.at(target).round().max(0.0) as i32);
+
+    Some(PassingScoreForecast {
+        program_code: program.code.clone(),
+        forecast_date: last.date.clone(),
+        final_date: final_date.to_string(),
+        points: points.len() as i32,
+        trend: trend.as_ref().map_or(0.0, |t| t.slope),
+        passing_score,
+        low: passing_score.zip(margin).map(|(p, m)| (p as f64 - m).round().max(0.0) as i32),
+        high: passing_score.zip(margin).map(|(p, m)| (p as f64 + m).round() as i32),
+        projected_consents,
+        shortage_expected: projected_consents.is_some_and(|c| c < program.places),
+    })
+}
+
+/// Прогнозы по всем направлениям, для которых есть история.
+pub fn passing_score_forecasts(
+    programs: &[Program],
+    history: &BTreeMap<String, Vec<HistoryPoint>>,
+    final_date: &str,
+) -> Vec<PassingScoreForecast> {
+    programs
+        .iter()
+        .filter_map(|program| passing_score_forecast(program, history.get(&program.code)?, final_date))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::tests::{applicant, numbered, program};
+
+    #[test]
+    fn consent_rates_extend_observed_share() {
+        let mut before = numbered(vec![applicant(80, &["A"], None), applicant(70, &["A"], None)]);
+        for app in &mut before {
+            app.agreed = false;
+        }
+        let mut current = before.clone();
+        current[0].agreed = true;
+
+        // За 10 дней согласие подала половина; еще 10 дней — та же половина оставшихся
+        let rates = consent_rates(Some(("2026-07-01", &before)), "2026-07-11", &current, "2026-07-21");
+        assert!((rates.overall - 0.5).abs() < 1e-9);
+        // В группе "A" меньше `MIN_CONSENT_GROUP` человек — общая вероятность
+        assert!(rates.by_program.is_empty());
+        assert_eq!(rates.for_applicant(&current[1]), rates.overall);
+        assert_eq!(consent_rates(Some(("2026-07-01", &before)), "2026-07-11", &current, "2026-07-11").overall, 0.0);
+        // Без истории — текущая доля согласий
+        assert_eq!(consent_rates(None, "2026-07-11", &current, "2026-07-21").overall, 0.5);
+    }
+
+    #[test]
+    fn consent_rates_differ_by_first_priority() {
+        // На "A" за 10 дней согласие подали все, на "B" — никто
+        let mut before: Vec<Applicant> = (0..20)
+            .map(|i| applicant(70, if i < 10 { &["A", "B"] } else { &["B", "A"] }, None))
+            .collect();
+        for app in &mut before {
+            app.agreed = false;
+        }
+        let before = numbered(before);
+        let mut current = before.clone();
+        for app in &mut current[..10] {
+            app.agreed = true;
+        }
+
+        let rates = consent_rates(Some(("2026-07-01", &before)), "2026-07-11", &current, "2026-07-21");
+        assert_eq!(rates.by_program["A"], 1.0);
+        assert_eq!(rates.by_program["B"], 0.0);
+        assert!((rates.overall - 0.5).abs() < 1e-9);
+        assert_eq!(rates.for_applicant(&current[15]), 0.0);
+    }
+
+    #[test]
+    fn forecast_counts_admissions_per_priority() {
+        let programs = vec![program("A", 1, &[]), program("B", 1, &[])];
+        let mut applicants = vec![applicant(90, &["A", "B"], None), applicant(80, &["A", "B"], None)];
+        applicants[0].agreed = false;
+
+        // Сильный подает согласие всегда, никогда или в части прогонов
+        let forecast = admission_forecast(&applicants, &programs, &StagePlan::default(), &[1.0; 2], 20, 7);
+        assert_eq!(forecast[0].choices[0].probability, 1.0);
+        assert_eq!(forecast[1].choices[1].budget_probability, 1.0);
+
+        let forecast = admission_forecast(&applicants, &programs, &StagePlan::default(), &[0.0; 2], 20, 7);
+        assert_eq!(forecast[0].admitted_probability, 0.0);
+        assert_eq!(forecast[1].choices[0].probability, 1.0);
+
+        let forecast = admission_forecast(&applicants, &programs, &StagePlan::default(), &[0.5; 2], 200, 7);
+        let p = forecast[0].choices[0].probability;
+        assert!(p > 0.3 && p < 0.7);
+        assert_eq!(forecast[1].admitted_probability, 1.0);
+        assert!((forecast[1].choices[0].probability + p - 1.0).abs() < 1e-9);
+        // Воспроизводимость по `seed`
+        let again = admission_forecast(&applicants, &programs, &StagePlan::default(), &[0.5; 2], 200, 7);
+        assert_eq!(again[0].choices[0].probability, p);
+    }
+
+    #[test]
+    fn passing_score_follows_trend_of_full_days() {
+        let point = |date: &str, passing_score: i32, places_filled: i32, consents: i32| HistoryPoint {
+            date: date.to_string(),
+            passing_score,
+            places_filled,
+            consents,
+            ..Default::default()
+        };
+        // Первый день — недобор, его балл в тренд не входит; дальше около +2 баллов в день
+        let history = vec![
+            point("2026-07-20", 150, 5, 8),
+            point("2026-07-21", 200, 10, 12),
+            point("2026-07-22", 202, 10, 16),
+            point("2026-07-23", 205, 10, 20),
+            point("2026-07-24", 206, 10, 24),
+        ];
+        let forecast = passing_score_forecast(&program("A", 10, &[]), &history, "2026-07-30").unwrap();
+
+        assert_eq!(forecast.points, 4);
+        assert_eq!(forecast.forecast_date, "2026-07-24");
+        assert!((forecast.trend - 2.1).abs() < 1e-9);
+        assert_eq!(forecast.passing_score, Some(219));
+        let (low, high) = (forecast.low.unwrap(), forecast.high.unwrap());
+        assert!(low < 219 && high > 219);
+        assert_eq!(forecast.projected_consents, Some(48));
+        assert!(!forecast.shortage_expected);
+
+        // Один день истории — без тренда и интервала
+        let forecast = passing_score_forecast(&program("A", 50, &[]), &history[..1], "2026-07-30").unwrap();
+        assert_eq!((forecast.passing_score, forecast.low), (Some(150), None));
+        assert!(forecast.shortage_expected);
+    }
+}
@@ -1,14 +1,14 @@
 use axum::{
-    extract::{Multipart, Query, State},
+    extract::{Multipart, Path, Query, State},
+    http::StatusCode,
     Json,
 };
 use serde_json::json;
-use std::collections::HashMap;
-use csv::ReaderBuilder;
-use sqlx::Row;
-use chrono::Local;
-use crate::{models::*, AppState, db, logic};
-use crate::db::NewApplicant;
+use std::collections::{BTreeMap, HashSet};
+use chrono::{Local, NaiveDate};
+use crate::{models::*, AppState, db, engine, forecast, import, logic};
+use crate::import::ParsedFile;
+use crate::jobs::{Job, JobStatus};
 
 #[derive(serde::Deserialize)]
 pub struct PaginationQuery {
@@ -18,202 +18,1011 @@ pub struct PaginationQuery {
     pub agreed: Option<bool>,
     pub program: Option<String>,
     pub min_score: Option<i32>,
+    pub date: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct DateQuery {
+    pub date: Option<String>,
 }
 
 pub async fn get_applicants(
     State(state): State<AppState>,
     Query(params): Query<PaginationQuery>,
-) -> Json<ApplicantListResponse> {
+) -> Result<Json<ApplicantListResponse>, StatusCode> {
 
-    let page = params.page.unwrap_or(1);
-    let limit = params.limit.unwrap_or(50) as i32;
+    let page = params.page.unwrap_or(1).max(1);
+    let limit = params.limit.unwrap_or(50).max(1) as i32;
     let offset = ((page - 1) * (limit as usize)) as i32;
-    let search_term = params.search.clone();
-
-    let applicants = db::get_applicants(
-        &state.db, 
-        limit, 
-        offset, 
-        search_term.clone(),
-        params.agreed,
-        params.program.clone(),
-        params.min_score
-    )
-        .await
-        .unwrap_or_else(|e| {
-            println!("DB Error: {}", e);
-            vec![]
-        });
 
-    let total_items = db::count_applicants(
-        &state.db, 
-        search_term,
-        params.agreed,
-        params.program,
-        params.min_score
-    )
+    let snapshot = db::resolve_snapshot(&state.db, params.date.as_deref())
         .await
-        .unwrap_or(0) as usize;
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let filter = db::ApplicantFilter {
+        search: params.search,
+        agreed: params.agreed,
+        program: params.program,
+        min_score: params.min_score,
+    };
+
+    // С фильтром по направлению список идет в порядке его конкурсного рейтинга
+    let (applicants, total_items) = match snapshot {
+        Some(snapshot) => {
+            let applicants = db::get_applicants(&state.db, snapshot.id, &filter, limit, offset)
+                .await
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+            let total_items = db::count_applicants(&state.db, snapshot.id, &filter)
+                .await
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)? as usize;
+
+            (applicants, total_items)
+        }
+        None => (vec![], 0),
+    };
 
     let total_pages = (total_items as f64 / limit as f64).ceil() as usize;
 
-    Json(ApplicantListResponse {
+    Ok(Json(ApplicantListResponse {
         data: applicants,
         meta: PaginationMeta {
             total_items,
             current_page: page,
             total_pages,
         },
+    }))
+}
+
+pub async fn get_stats(
+    State(state): State<AppState>,
+    Query(params): Query<DateQuery>,
+) -> Result<Json<Vec<ProgramStats>>, StatusCode> {
+    let snapshot = db::resolve_snapshot(&state.db, params.date.as_deref())
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    logic::get_detailed_stats(&state.db, snapshot.map(|s| s.id))
+        .await
+        .map(Json)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+pub async fn list_snapshots(State(state): State<AppState>) -> Result<Json<Vec<Snapshot>>, StatusCode> {
+    db::list_snapshots(&state.db)
+        .await
+        .map(Json)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+#[derive(serde::Deserialize)]
+pub struct DiffQuery {
+    pub from: String,
+    pub to: String,
+}
+
+pub async fn get_diff(
+    State(state): State<AppState>,
+    Query(params): Query<DiffQuery>,
+) -> Result<Json<SnapshotDiff>, StatusCode> {
+    let mut lists = Vec::with_capacity(2);
+
+    for date in [&params.from, &params.to] {
+        let snapshot = match db::snapshot_by_date(&state.db, date).await {
+            Ok(Some(s)) => s,
+            Ok(None) => return Err(StatusCode::NOT_FOUND),
+            Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+        };
+        let applicants = db::get_all_applicants(&state.db, snapshot.id)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        lists.push(applicants);
+    }
+
+    let after = lists.pop().unwrap_or_default();
+    let before = lists.pop().unwrap_or_default();
+
+    Ok(Json(logic::diff_applicants(&params.from, &params.to, before, after)))
+}
+
+/// Повторный прогон распределения на срезе за прошедшую дату.
+pub async fn recalculate_snapshot(
+    State(state): State<AppState>,
+    Path(date): Path<String>,
+) -> Result<Json<Vec<ProgramStats>>, StatusCode> {
+    let snapshot = match db::snapshot_by_date(&state.db, &date).await {
+        Ok(Some(s)) => s,
+        Ok(None) => return Err(StatusCode::NOT_FOUND),
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    };
+
+    run_recalculation(&state, snapshot.id, &snapshot.report_date)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    logic::get_detailed_stats(&state.db, Some(snapshot.id))
+        .await
+        .map(Json)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// Пересчет среза задачей из очереди с ожиданием итога: пересчеты
+/// выполняются строго по одному. `Err` — текст ошибки задачи.
+async fn run_recalculation(state: &AppState, snapshot_id: i64, report_date: &str) -> Result<(), String> {
+    let (job_id, handle) = state.jobs.spawn_recalculation(state.db.clone(), snapshot_id, report_date.to_string());
+    match state.jobs.wait(job_id, handle).await {
+        Some(job) if job.status == JobStatus::Failed => Err(job.error.unwrap_or_default()),
+        _ => Ok(()),
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct ImportQuery {
+    pub wait: Option<bool>,
+    // Отклонить весь файл, если хотя бы одна строка не прошла проверку
+    pub strict: Option<bool>,
+    // Только показать, что изменится, ничего не записывая
+    pub dry_run: Option<bool>,
+    #[serde(default)]
+    pub mode: ImportMode,
+}
+
+fn import_error(message: String, stats: ImportStats, errors: Vec<RowError>) -> Json<ImportResponse> {
+    Json(ImportResponse {
+        status: "error".to_string(),
+        message,
+        stats,
+        warning: None,
+        job_id: None,
+        job: None,
+        errors,
+        preview: None,
     })
 }
 
-pub async fn get_stats(State(state): State<AppState>) -> Json<Vec<ProgramStats>> {
-    let stats = logic::get_detailed_stats(&state.db).await;
-    Json(stats)
+/// Профиль сопоставления колонок по имени из формы.
+async fn load_profile(state: &AppState, name: Option<&str>) -> Result<Option<ImportProfile>, String> {
+    let Some(name) = name else {
+        return Ok(None);
+    };
+
+    match db::get_profile(&state.db, name).await {
+        Ok(Some(profile)) => Ok(Some(profile)),
+        Ok(None) => Err(format!("Профиль импорта '{}' не найден", name)),
+        Err(e) => Err(format!("Ошибка БД: {}", e)),
+    }
 }
 
 pub async fn import_data(
     State(state): State<AppState>,
+    Query(params): Query<ImportQuery>,
     mut multipart: Multipart
 ) -> Json<ImportResponse> {
 
-    let prev_count = db::count_applicants(&state.db, None, None, None, None).await.unwrap_or(0);
-    let mut stats = ImportStats { processed: 0 };
     let mut report_date = Local::now().format("%Y-%m-%d").to_string();
-    
-    let mut applicants_buffer: Vec<NewApplicant> = Vec::with_capacity(12000);
+    let mut file: Option<(Option<String>, axum::body::Bytes)> = None;
+    let mut sheet: Option<String> = None;
+    let mut profile_name: Option<String> = None;
 
-    while let Some(field) = multipart.next_field().await.unwrap() {
-        let name = field.name().unwrap().to_string();
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(f)) => f,
+            Ok(None) => break,
+            Err(e) => {
+                return import_error(format!("Ошибка чтения формы: {}", e), ImportStats { processed: 0, rejected: 0 }, vec![]);
+            }
+        };
+        let name = field.name().unwrap_or_default().to_string();
 
         if name == "date" {
-            if let Ok(text) = field.text().await {
-                if !text.is_empty() {
-                    report_date = text;
+            let text = field.text().await.unwrap_or_default();
+            if text.trim().is_empty() {
+                continue;
+            }
+            // Срезы сравниваются по строке даты, поэтому только `YYYY-MM-DD`
+            match NaiveDate::parse_from_str(text.trim(), "%Y-%m-%d") {
+                Ok(date) => report_date = date.format("%Y-%m-%d").to_string(),
+                Err(_) => {
+                    return import_error(
+                        format!("Некорректная дата '{}': нужен формат YYYY-MM-DD", text),
+                        ImportStats { processed: 0, rejected: 0 },
+                        vec![],
+                    );
                 }
             }
             continue;
         }
 
+        if name == "sheet" {
+            sheet = field.text().await.ok();
+            continue;
+        }
+
+        if name == "profile" {
+            profile_name = field.text().await.ok().filter(|t| !t.is_empty());
+            continue;
+        }
+
         if name == "file" {
-            let data = field.bytes().await.unwrap();
-            let mut rdr = ReaderBuilder::new()
-                .delimiter(b',')
-                .from_reader(&data[..]);
-
-            for result in rdr.deserialize() {
-                let record: CsvApplicant = match result {
-                    Ok(r) => r,
-                    Err(e) => {
-                        println!("Ошибка парсинга: {}", e);
-                        continue;
-                    }
-                };
-
-                let val = record.agreed.trim().to_lowercase();
-                let is_agreed = val == "true" || val == "1" || val == "да" || val == "+";
-
-                let priorities_vec: Vec<String> = record.priorities
-                    .split(';')
-                    .map(|s| s.trim().to_string())
-                    .filter(|s| !s.is_empty())
-                    .collect();
-
-                let new_applicant = NewApplicant {
-                    external_id: record.id,
-                    full_name: record.name,
-                    score_math: record.math,
-                    score_rus: record.rus,
-                    score_phys: record.phys,
-                    score_achieve: record.achieve,
-                    agreed: is_agreed,
-                    priorities: priorities_vec,
-                };
-
-                applicants_buffer.push(new_applicant);
+            // Номера строк в ошибках и проверка дубликатов id — в пределах одного файла
+            if file.is_some() {
+                return import_error(
+                    "Передано несколько файлов, импорт принимает один файл за запрос".to_string(),
+                    ImportStats { processed: 0, rejected: 0 },
+                    vec![],
+                );
+            }
+            let file_name = field.file_name().map(|n| n.to_string());
+            match field.bytes().await {
+                Ok(data) => file = Some((file_name, data)),
+                Err(e) => {
+                    return import_error(format!("Ошибка чтения файла: {}", e), ImportStats { processed: 0, rejected: 0 }, vec![]);
+                }
             }
         }
     }
 
-    stats.processed = applicants_buffer.len() as i32;
+    let profile = match load_profile(&state, profile_name.as_deref()).await {
+        Ok(p) => p,
+        Err(message) => return import_error(message, ImportStats { processed: 0, rejected: 0 }, vec![]),
+    };
 
-    match db::import_batch(&state.db, applicants_buffer).await {
-        Ok(_) => (),
+    let known_programs: HashSet<String> = match db::list_programs(&state.db).await {
+        Ok(list) => list.into_iter().map(|p| p.code).collect(),
         Err(e) => {
-            return Json(ImportResponse {
-                status: "error".to_string(),
-                message: format!("Ошибка БД: {}", e),
-                stats: ImportStats { processed: 0 },
-                warning: None,
-            });
+            return import_error(format!("Ошибка БД: {}", e), ImportStats { processed: 0, rejected: 0 }, vec![]);
         }
+    };
+
+    let Some((file_name, data)) = file else {
+        return import_error("Файл не передан".to_string(), ImportStats { processed: 0, rejected: 0 }, vec![]);
+    };
+
+    let source = match import::read_source(&data, file_name.as_deref(), sheet.as_deref()) {
+        Ok(s) => s,
+        Err(e) => {
+            return import_error(e, ImportStats { processed: 0, rejected: 0 }, vec![]);
+        }
+    };
+
+    let parsed = match params.mode {
+        ImportMode::Delete => import::parse_ids(&source, profile.as_ref()),
+        _ => import::parse_csv(&source, &known_programs, profile.as_ref()),
+    };
+    let ParsedFile { applicants: applicants_buffer, errors, rejected_ids } = match parsed {
+        Ok(p) => p,
+        Err(e) => {
+            return import_error(e, ImportStats { processed: 0, rejected: 0 }, vec![]);
+        }
+    };
+
+    let rejected_rows: HashSet<u64> = errors.iter().map(|e| e.line).collect();
+    let stats = ImportStats {
+        processed: applicants_buffer.len() as i32,
+        rejected: rejected_rows.len() as i32,
+    };
+
+    if params.strict.unwrap_or(false) && !errors.is_empty() {
+        return import_error(
+            format!("Файл отклонен: ошибки в {} строках", stats.rejected),
+            ImportStats { processed: 0, rejected: stats.rejected },
+            errors,
+        );
+    }
+
+    // Без единой валидной строки `replace` очистил бы срез
+    if applicants_buffer.is_empty() {
+        return import_error(
+            "В файле нет ни одной корректной строки, срез не создан".to_string(),
+            stats,
+            errors,
+        );
     }
 
-    let pool_clone = state.db.clone();
-    let date_clone = report_date.clone();
+    if params.dry_run.unwrap_or(false) {
+        let preview = match logic::preview_import(&state.db, &report_date, params.mode, &applicants_buffer, &rejected_ids).await {
+            Ok(p) => p,
+            Err(e) => return import_error(format!("Ошибка БД: {}", e), stats, errors),
+        };
 
-    tokio::spawn(async move {
-        logic::recalculate_admissions(&pool_clone, &date_clone).await;
-    });
+        let warning = (!errors.is_empty())
+            .then(|| format!("Пропущено строк с ошибками: {}", stats.rejected));
+
+        return Json(ImportResponse {
+            status: "success".to_string(),
+            message: format!("Предпросмотр: {} записей, данные не изменены", stats.processed),
+            stats,
+            warning,
+            job_id: None,
+            job: None,
+            errors,
+            preview: Some(preview),
+        });
+    }
+
+    let snapshot_id = match db::import_batch(&state.db, &report_date, params.mode, applicants_buffer, &rejected_ids).await {
+        Ok(id) => id,
+        Err(e) => {
+            return import_error(format!("Ошибка БД: {}", e), ImportStats { processed: 0, rejected: stats.rejected }, errors);
+        }
+    };
 
-    let new_count = db::count_applicants(&state.db, None, None, None, None).await.unwrap_or(0);
-    
-    let warning: Option<String> = None;
+    let (job_id, handle) = state.jobs.spawn_recalculation(state.db.clone(), snapshot_id, report_date.clone());
+
+    let job = if params.wait.unwrap_or(false) {
+        state.jobs.wait(job_id, handle).await
+    } else {
+        None
+    };
+
+    if let Some(failed) = job.as_ref().filter(|j| j.status == JobStatus::Failed) {
+        return Json(ImportResponse {
+            status: "error".to_string(),
+            message: format!("Ошибка пересчета: {}", failed.error.clone().unwrap_or_default()),
+            stats,
+            warning: None,
+            job_id: Some(job_id),
+            job,
+            errors,
+            preview: None,
+        });
+    }
+
+    let warning = (!errors.is_empty())
+        .then(|| format!("Пропущено строк с ошибками: {}", stats.rejected));
 
     Json(ImportResponse {
         status: "success".to_string(),
         message: format!("Обработано {} записей за дату {}", stats.processed, report_date),
         stats,
         warning,
+        job_id: Some(job_id),
+        job,
+        errors,
+        preview: None,
     })
 }
 
-pub async fn get_history(State(state): State<AppState>) -> Json<HashMap<String, Vec<serde_json::Value>>> {
-    let rows = sqlx::query(
-        "SELECT program_code, record_date, passing_score FROM history_stats ORDER BY record_date ASC"
+pub async fn get_job(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<Json<Job>, StatusCode> {
+    state.jobs.get(id).map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+/// История по направлениям: статистика каждого пересчета в порядке дат.
+pub async fn get_history(State(state): State<AppState>) -> Result<Json<BTreeMap<String, Vec<HistoryPoint>>>, StatusCode> {
+    db::get_history(&state.db, None)
+        .await
+        .map(Json)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+pub async fn clear_db(State(state): State<AppState>) -> StatusCode {
+    match db::clear_all(&state.db).await {
+        Ok(_) => StatusCode::OK,
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct IntersectionQuery {
+    pub min_size: Option<usize>,
+    pub max_size: Option<usize>,
+    pub agreed: Option<bool>,
+}
+
+pub async fn get_intersections(
+    State(state): State<AppState>,
+    Query(params): Query<IntersectionQuery>,
+) -> Result<Json<Vec<IntersectionEntry>>, (StatusCode, Json<serde_json::Value>)> {
+    let min_size = params.min_size.unwrap_or(2).max(1);
+    let max_size = params.max_size.unwrap_or(logic::MAX_INTERSECTION_SIZE);
+    if max_size > logic::MAX_INTERSECTION_SIZE || min_size > max_size {
+        let message = format!("Размер набора должен быть от 1 до {}", logic::MAX_INTERSECTION_SIZE);
+        return Err((StatusCode::BAD_REQUEST, Json(json!({ "message": message }))));
+    }
+
+    let entries = logic::calculate_intersections(
+        &state.db,
+        min_size,
+        max_size,
+        params.agreed.unwrap_or(false),
     )
-        .fetch_all(&state.db)
+    .await
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "message": "Ошибка БД" }))))?;
+
+    Ok(Json(entries))
+}
+
+/// Коды предметов корректны, пороги заданы только по предметам направления и лежат в 0..`MAX_SCORE`,
+/// квоты известных категорий и в сумме не больше числа мест, правила равенства известны.
+fn valid_requirements(
+    places: i32,
+    subjects: &[String],
+    min_scores: &BTreeMap<String, i32>,
+    quotas: &BTreeMap<String, i32>,
+    tie_break: &[String],
+) -> bool {
+    let codes: Vec<&str> = subjects.iter().flat_map(|slot| engine::slot_subjects(slot)).collect();
+
+    places >= 0
+        && !subjects.is_empty()
+        && codes.iter().all(|s| is_subject_code(s))
+        && min_scores.iter().all(|(s, min)| codes.contains(&s.as_str()) && (0..=import::MAX_SCORE).contains(min))
+        && quotas.iter().all(|(q, seats)| QUOTAS.contains(&q.as_str()) && *seats >= 0)
+        && quotas.values().sum::<i32>() <= places
+        && tie_break.iter().all(|rule| engine::is_tie_break_rule(rule))
+}
+
+pub async fn list_programs(State(state): State<AppState>) -> Result<Json<Vec<Program>>, StatusCode> {
+    db::list_programs(&state.db)
         .await
-        .unwrap_or_default();
+        .map(Json)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
 
-    let mut history: HashMap<String, Vec<serde_json::Value>> = HashMap::new();
+pub async fn create_program(
+    State(state): State<AppState>,
+    Json(program): Json<Program>,
+) -> Result<(StatusCode, Json<Program>), StatusCode> {
+    if program.code.trim().is_empty()
+        || program.contract_places < 0
+        || !valid_requirements(program.places, &program.subjects, &program.min_scores, &program.quotas, &program.tie_break)
+    {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    match db::get_program(&state.db, &program.code).await {
+        Ok(Some(_)) => return Err(StatusCode::CONFLICT),
+        Ok(None) => (),
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
 
-    for row in rows {
-        let code: String = row.get("program_code");
-        let date: String = row.get("record_date");
-        let score: i32 = row.get("passing_score");
+    db::insert_program(&state.db, &program)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
-        history.entry(code).or_default().push(json!({
-            "date": date,
-            "score": score
-        }));
+    Ok((StatusCode::CREATED, Json(program)))
+}
+
+pub async fn update_program(
+    State(state): State<AppState>,
+    Path(code): Path<String>,
+    Json(update): Json<ProgramUpdate>,
+) -> Result<Json<Program>, StatusCode> {
+    if update.contract_places < 0
+        || !valid_requirements(update.places, &update.subjects, &update.min_scores, &update.quotas, &update.tie_break)
+    {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    match db::update_program(&state.db, &code, &update).await {
+        Ok(true) => (),
+        Ok(false) => return Err(StatusCode::NOT_FOUND),
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
     }
 
-    Json(history)
+    Ok(Json(Program {
+        code,
+        name: update.name,
+        places: update.places,
+        contract_places: update.contract_places,
+        subjects: update.subjects,
+        min_scores: update.min_scores,
+        quotas: update.quotas,
+        tie_break: update.tie_break,
+    }))
 }
 
-pub async fn clear_db(State(state): State<AppState>) -> axum::http::StatusCode {
-    match db::clear_all(&state.db).await {
-        Ok(_) => axum::http::StatusCode::OK,
-        Err(_) => axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-    }
-}
-
-pub async fn get_intersections(State(state): State<AppState>) -> Json<Box<IntersectionStats>> {
-    let counts = logic::calculate_intersections(&state.db).await;
-    
-    Json(Box::new(IntersectionStats {
-        pm_ivt: *counts.get("pm_ivt").unwrap_or(&0) as i32,
-        pm_itss: *counts.get("pm_itss").unwrap_or(&0) as i32,
-        pm_ib: *counts.get("pm_ib").unwrap_or(&0) as i32,
-        ivt_itss: *counts.get("ivt_itss").unwrap_or(&0) as i32,
-        ivt_ib: *counts.get("ivt_ib").unwrap_or(&0) as i32,
-        itss_ib: *counts.get("itss_ib").unwrap_or(&0) as i32,
-
-        pm_ivt_itss: *counts.get("pm_ivt_itss").unwrap_or(&0) as i32,
-        pm_ivt_ib: *counts.get("pm_ivt_ib").unwrap_or(&0) as i32,
-        ivt_itss_ib: *counts.get("ivt_itss_ib").unwrap_or(&0) as i32,
-        pm_itss_ib: *counts.get("pm_itss_ib").unwrap_or(&0) as i32,
-        all_four: *counts.get("all_four").unwrap_or(&0) as i32,
+/// "Что будет, если": распределение после гипотетических изменений в памяти,
+/// в сравнении с текущим состоянием среза. БД не изменяется.
+pub async fn simulate(
+    State(state): State<AppState>,
+    Json(request): Json<SimulationRequest>,
+) -> Result<Json<SimulationResult>, (StatusCode, Json<serde_json::Value>)> {
+    let internal = |_| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "message": "Ошибка БД" })));
+
+    let snapshot = db::resolve_snapshot(&state.db, request.date.as_deref()).await.map_err(internal)?;
+    if request.date.is_some() && snapshot.is_none() {
+        return Err((StatusCode::NOT_FOUND, Json(json!({ "message": "Срез не найден" }))));
+    }
+
+    let current = match &snapshot {
+        Some(s) => db::get_all_applicants(&state.db, s.id).await.map_err(internal)?,
+        None => Vec::new(),
+    };
+    let programs = db::list_programs(&state.db).await.map_err(internal)?;
+    let plan = match &snapshot {
+        Some(s) => logic::load_stage_plan(&state.db, &s.report_date).await.map_err(internal)?,
+        None => engine::StagePlan::default(),
+    };
+
+    logic::simulate(&programs, &current, snapshot.map(|s| s.report_date), &plan, &request.changes)
+        .map(Json)
+        .map_err(|message| (StatusCode::BAD_REQUEST, Json(json!({ "message": message }))))
+}
+
+#[derive(serde::Deserialize)]
+pub struct ForecastQuery {
+    pub date: Option<String>,
+    // Окончание приема согласий; по умолчанию срок последнего этапа зачисления
+    pub final_date: Option<String>,
+    pub runs: Option<u32>,
+    pub seed: Option<u64>,
+    // Только один абитуриент
+    pub external_id: Option<i32>,
+}
+
+const DEFAULT_FORECAST_RUNS: u32 = 200;
+const MAX_FORECAST_RUNS: u32 = 2000;
+
+/// Вероятность зачисления каждого абитуриента по каждому приоритету к окончанию
+/// приема согласий. Интенсивность подачи согласий оценивается по предыдущему срезу.
+pub async fn get_admission_forecast(
+    State(state): State<AppState>,
+    Query(params): Query<ForecastQuery>,
+) -> Result<Json<AdmissionForecast>, (StatusCode, Json<serde_json::Value>)> {
+    let internal = |_| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "message": "Ошибка БД" })));
+    let bad_request = |message: &str| (StatusCode::BAD_REQUEST, Json(json!({ "message": message })));
+
+    let runs = params.runs.unwrap_or(DEFAULT_FORECAST_RUNS);
+    if !(1..=MAX_FORECAST_RUNS).contains(&runs) {
+        return Err(bad_request(&format!("Число прогонов должно быть от 1 до {}", MAX_FORECAST_RUNS)));
+    }
+
+    let snapshot = db::resolve_snapshot(&state.db, params.date.as_deref())
+        .await
+        .map_err(internal)?
+        .ok_or((StatusCode::NOT_FOUND, Json(json!({ "message": "Срез не найден" }))))?;
+
+    let stages = db::list_stages(&state.db).await.map_err(internal)?;
+    let final_date = params
+        .final_date
+        .or_else(|| stages.last().map(|s| s.consent_deadline.clone()))
+        .ok_or_else(|| bad_request("Не задана дата окончания приема согласий (final_date) и нет этапов зачисления"))?;
+    if forecast::days_between(&snapshot.report_date, &final_date).is_none() {
+        return Err(bad_request("Дата окончания должна быть в формате YYYY-MM-DD"));
+    }
+
+    let mut applicants = db::get_all_applicants(&state.db, snapshot.id).await.map_err(internal)?;
+    if let Some(id) = params.external_id
+        && !applicants.iter().any(|a| a.external_id == id)
+    {
+        return Err((StatusCode::NOT_FOUND, Json(json!({ "message": "Абитуриент не найден" }))));
+    }
+
+    // Интенсивность согласий — по изменениям с предыдущего среза
+    let previous = db::list_snapshots(&state.db)
+        .await
+        .map_err(internal)?
+        .into_iter()
+        .rfind(|s| s.report_date < snapshot.report_date);
+    let earlier = match &previous {
+        Some(s) => Some(db::get_all_applicants(&state.db, s.id).await.map_err(internal)?),
+        None => None,
+    };
+    let rates = forecast::consent_rates(
+        previous.as_ref().map(|s| s.report_date.as_str()).zip(earlier.as_deref()),
+        &snapshot.report_date,
+        &applicants,
+        &final_date,
+    );
+
+    let programs = db::list_programs(&state.db).await.map_err(internal)?;
+    let plan = logic::load_stage_plan(&state.db, &final_date).await.map_err(internal)?;
+    let seed = params.seed.unwrap_or(0);
+
+    // Прогоны распределения занимают процессор — вне асинхронных потоков
+    applicants.sort_by_key(|a| a.external_id);
+    let probabilities: Vec<f64> = applicants.iter().map(|a| rates.for_applicant(a)).collect();
+    let mut forecasts = tokio::task::spawn_blocking(move || {
+        forecast::admission_forecast(&applicants, &programs, &plan, &probabilities, runs, seed)
+    })
+    .await
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "message": "Ошибка расчета прогноза" }))))?;
+    if let Some(id) = params.external_id {
+        forecasts.retain(|f| f.external_id == id);
+    }
+
+    Ok(Json(AdmissionForecast {
+        date: snapshot.report_date,
+        final_date,
+        consent_probability: rates.overall,
+        consent_probability_by_program: rates.by_program,
+        runs,
+        applicants: forecasts,
     }))
-}
\ No newline at end of file
+}
+
+#[derive(serde::Deserialize)]
+pub struct PassingForecastQuery {
+    // История до этой даты включительно (def: вся)
+    pub date: Option<String>,
+    pub final_date: Option<String>,
+}
+
+/// Прогноз проходного балла по направлениям на окончание приема согласий
+/// по тренду `history_stats`. Ничего не сохраняет.
+pub async fn get_passing_score_forecast(
+    State(state): State<AppState>,
+    Query(params): Query<PassingForecastQuery>,
+) -> Result<Json<Vec<PassingScoreForecast>>, (StatusCode, Json<serde_json::Value>)> {
+    let internal = |_| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "message": "Ошибка БД" })));
+    let bad_request = |message: &str| (StatusCode::BAD_REQUEST, Json(json!({ "message": message })));
+
+    let stages = db::list_stages(&state.db).await.map_err(internal)?;
+    let final_date = params
+        .final_date
+        .or_else(|| stages.last().map(|s| s.consent_deadline.clone()))
+        .ok_or_else(|| bad_request("Не задана дата окончания приема согласий (final_date) и нет этапов зачисления"))?;
+    if chrono::NaiveDate::parse_from_str(&final_date, "%Y-%m-%d").is_err() {
+        return Err(bad_request("Дата окончания должна быть в формате YYYY-MM-DD"));
+    }
+
+    let history = db::get_history(&state.db, params.date.as_deref().filter(|d| !d.is_empty()))
+        .await
+        .map_err(internal)?;
+    let programs = db::list_programs(&state.db).await.map_err(internal)?;
+
+    Ok(Json(forecast::passing_score_forecasts(&programs, &history, &final_date)))
+}
+
+/// Сохраненные при пересчетах прогнозы проходного балла в сравнении с фактом.
+pub async fn get_forecast_accuracy(State(state): State<AppState>) -> Result<Json<Vec<ForecastAccuracy>>, StatusCode> {
+    db::list_forecast_accuracy(&state.db)
+        .await
+        .map(Json)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// Карточка абитуриента в срезе: место и проходной балл по каждому приоритету
+/// и причина текущего зачисления.
+pub async fn get_applicant(
+    State(state): State<AppState>,
+    Path(external_id): Path<i32>,
+    Query(params): Query<DateQuery>,
+) -> Result<Json<ApplicantDetail>, StatusCode> {
+    let snapshot = db::resolve_snapshot(&state.db, params.date.as_deref())
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let applicants = db::get_all_applicants(&state.db, snapshot.id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let idx = applicants
+        .iter()
+        .position(|a| a.external_id == external_id)
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let programs = db::list_programs(&state.db)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(logic::explain_admission(idx, &programs, &applicants)))
+}
+
+/// Конкурсный список направления в срезе с критериями, по которым определено место.
+pub async fn get_program_ranking(
+    State(state): State<AppState>,
+    Path(code): Path<String>,
+    Query(params): Query<DateQuery>,
+) -> Result<Json<ProgramRanking>, StatusCode> {
+    let program = match db::get_program(&state.db, &code).await {
+        Ok(Some(p)) => p,
+        Ok(None) => return Err(StatusCode::NOT_FOUND),
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    };
+
+    let snapshot = db::resolve_snapshot(&state.db, params.date.as_deref())
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let applicants = match snapshot {
+        Some(s) => db::get_all_applicants(&state.db, s.id)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+        None => Vec::new(),
+    };
+
+    Ok(Json(logic::program_ranking(&program, &applicants)))
+}
+
+pub async fn delete_program(
+    State(state): State<AppState>,
+    Path(code): Path<String>,
+) -> StatusCode {
+    match db::delete_program(&state.db, &code).await {
+        Ok(true) => StatusCode::NO_CONTENT,
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// Срок в формате `YYYY-MM-DD`, доля мест в 0..100.
+fn valid_stage(consent_deadline: &str, places_percent: i32) -> bool {
+    chrono::NaiveDate::parse_from_str(consent_deadline, "%Y-%m-%d").is_ok() && (0..=100).contains(&places_percent)
+}
+
+/// Новый или измененный срок этапа не может быть раньше срока уже закрытого этапа.
+fn after_frozen(stages: &[EnrollmentStage], consent_deadline: &str) -> bool {
+    stages
+        .iter()
+        .filter(|s| s.frozen_at.is_some())
+        .all(|s| s.consent_deadline.as_str() < consent_deadline)
+}
+
+pub async fn list_stages(State(state): State<AppState>) -> Result<Json<Vec<EnrollmentStage>>, StatusCode> {
+    db::list_stages(&state.db)
+        .await
+        .map(Json)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+pub async fn create_stage(
+    State(state): State<AppState>,
+    Json(mut stage): Json<EnrollmentStage>,
+) -> Result<(StatusCode, Json<EnrollmentStage>), StatusCode> {
+    if stage.code.trim().is_empty() || !valid_stage(&stage.consent_deadline, stage.places_percent) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let stages = db::list_stages(&state.db)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    // Код и срок этапа уникальны
+    if stages.iter().any(|s| s.code == stage.code || s.consent_deadline == stage.consent_deadline)
+        || !after_frozen(&stages, &stage.consent_deadline)
+    {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    db::insert_stage(&state.db, &stage)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    stage.frozen_at = None;
+    stage.enrolled = 0;
+    Ok((StatusCode::CREATED, Json(stage)))
+}
+
+/// Закрытый этап не изменяется — сначала нужно отменить фиксацию.
+pub async fn update_stage(
+    State(state): State<AppState>,
+    Path(code): Path<String>,
+    Json(update): Json<StageUpdate>,
+) -> Result<Json<EnrollmentStage>, StatusCode> {
+    if !valid_stage(&update.consent_deadline, update.places_percent) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let stages = db::list_stages(&state.db)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let Some(stage) = stages.iter().find(|s| s.code == code) else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+    if stage.frozen_at.is_some()
+        || stages.iter().any(|s| s.code != code && s.consent_deadline == update.consent_deadline)
+        || !after_frozen(&stages, &update.consent_deadline)
+    {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    db::update_stage(&state.db, &code, &update)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(EnrollmentStage {
+        code,
+        name: update.name,
+        consent_deadline: update.consent_deadline,
+        places_percent: update.places_percent,
+        priority_only: update.priority_only,
+        frozen_at: None,
+        enrolled: 0,
+    }))
+}
+
+pub async fn delete_stage(
+    State(state): State<AppState>,
+    Path(code): Path<String>,
+) -> StatusCode {
+    let stages = match db::list_stages(&state.db).await {
+        Ok(stages) => stages,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    match stages.iter().find(|s| s.code == code) {
+        None => return StatusCode::NOT_FOUND,
+        Some(stage) if stage.frozen_at.is_some() => return StatusCode::CONFLICT,
+        Some(_) => (),
+    }
+
+    match db::delete_stage(&state.db, &code).await {
+        Ok(_) => StatusCode::NO_CONTENT,
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// Пересчитывает актуальный срез, если он позже срока согласий `consent_deadline`:
+/// зачисление закрытого этапа действует для срезов после его срока.
+async fn recalculate_after(state: &AppState, consent_deadline: &str) -> Result<(), String> {
+    if let Some(latest) = db::latest_snapshot(&state.db).await.map_err(|e| e.to_string())?
+        && latest.report_date.as_str() > consent_deadline
+    {
+        run_recalculation(state, latest.id, &latest.report_date).await?;
+    }
+    Ok(())
+}
+
+/// Фиксирует зачисление этапа по последнему срезу не позже срока приема согласий:
+/// срез пересчитывается по правилам этапа, зачисленные закрепляются за направлениями.
+/// Этапы закрываются по порядку.
+pub async fn freeze_stage(
+    State(state): State<AppState>,
+    Path(code): Path<String>,
+) -> Result<Json<EnrollmentStage>, (StatusCode, Json<serde_json::Value>)> {
+    let internal = |_| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "message": "Ошибка БД" })));
+    let failed = |e: String| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "message": format!("Ошибка пересчета: {}", e) })));
+    let conflict = |message: String| (StatusCode::CONFLICT, Json(json!({ "message": message })));
+
+    let stages = db::list_stages(&state.db).await.map_err(internal)?;
+    let Some(pos) = stages.iter().position(|s| s.code == code) else {
+        return Err((StatusCode::NOT_FOUND, Json(json!({ "message": "Этап не найден" }))));
+    };
+    let stage = &stages[pos];
+    if stage.frozen_at.is_some() {
+        return Err(conflict(format!("Этап {} уже закрыт", code)));
+    }
+    if let Some(open) = stages[..pos].iter().find(|s| s.frozen_at.is_none()) {
+        return Err(conflict(format!("Сначала нужно закрыть этап {}", open.code)));
+    }
+
+    let snapshot = db::base_snapshot(&state.db, &stage.consent_deadline)
+        .await
+        .map_err(internal)?
+        .ok_or_else(|| conflict(format!("Нет среза на {} или раньше", stage.consent_deadline)))?;
+
+    let (job_id, handle) =
+        state.jobs.spawn_stage_freeze(state.db.clone(), code.clone(), snapshot.id, snapshot.report_date.clone());
+    if let Some(job) = state.jobs.wait(job_id, handle).await.filter(|j| j.status == JobStatus::Failed) {
+        return Err(failed(job.error.unwrap_or_default()));
+    }
+    recalculate_after(&state, &stage.consent_deadline).await.map_err(failed)?;
+
+    let stages = db::list_stages(&state.db).await.map_err(internal)?;
+    stages
+        .into_iter()
+        .find(|s| s.code == code)
+        .map(Json)
+        .ok_or_else(|| internal(sqlx::Error::RowNotFound))
+}
+
+/// Отмена фиксации последнего закрытого этапа.
+pub async fn unfreeze_stage(
+    State(state): State<AppState>,
+    Path(code): Path<String>,
+) -> Result<StatusCode, (StatusCode, Json<serde_json::Value>)> {
+    let internal = |_| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "message": "Ошибка БД" })));
+    let failed = |e: String| (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "message": format!("Ошибка пересчета: {}", e) })));
+    let conflict = |message: String| (StatusCode::CONFLICT, Json(json!({ "message": message })));
+
+    let stages = db::list_stages(&state.db).await.map_err(internal)?;
+    let Some(pos) = stages.iter().position(|s| s.code == code) else {
+        return Err((StatusCode::NOT_FOUND, Json(json!({ "message": "Этап не найден" }))));
+    };
+    if stages[pos].frozen_at.is_none() {
+        return Err(conflict(format!("Этап {} не закрыт", code)));
+    }
+    if let Some(later) = stages[pos + 1..].iter().find(|s| s.frozen_at.is_some()) {
+        return Err(conflict(format!("Сначала нужно отменить фиксацию этапа {}", later.code)));
+    }
+
+    db::unfreeze_stage(&state.db, &code).await.map_err(internal)?;
+    recalculate_after(&state, &stages[pos].consent_deadline).await.map_err(failed)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Заголовки загруженного файла и предлагаемое сопоставление колонок (без импорта).
+pub async fn inspect_import(
+    State(state): State<AppState>,
+    mut multipart: Multipart,
+) -> Result<Json<ImportInspection>, (StatusCode, Json<serde_json::Value>)> {
+    let bad_request = |message: String| (StatusCode::BAD_REQUEST, Json(json!({ "message": message })));
+
+    let mut file: Option<(Option<String>, axum::body::Bytes)> = None;
+    let mut sheet: Option<String> = None;
+    let mut profile_name: Option<String> = None;
+
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| bad_request(format!("Ошибка чтения формы: {}", e)))?
+    {
+        match field.name().unwrap_or_default() {
+            "sheet" => sheet = field.text().await.ok(),
+            "profile" => profile_name = field.text().await.ok().filter(|t| !t.is_empty()),
+            "file" => {
+                let file_name = field.file_name().map(|n| n.to_string());
+                let data = field
+                    .bytes()
+                    .await
+                    .map_err(|e| bad_request(format!("Ошибка чтения файла: {}", e)))?;
+                file = Some((file_name, data));
+            }
+            _ => (),
+        }
+    }
+
+    let (file_name, data) = file.ok_or_else(|| bad_request("Файл не передан".to_string()))?;
+    let profile = load_profile(&state, profile_name.as_deref()).await.map_err(bad_request)?;
+
+    let source = import::read_source(&data, file_name.as_deref(), sheet.as_deref()).map_err(bad_request)?;
+
+    import::inspect(&source, profile.as_ref()).map(Json).map_err(bad_request)
+}
+
+fn valid_profile(profile: &ImportProfile) -> bool {
+    !profile.name.trim().is_empty()
+        && profile.columns.values().all(|field| import::is_mapping_target(field))
+}
+
+pub async fn list_profiles(State(state): State<AppState>) -> Result<Json<Vec<ImportProfile>>, StatusCode> {
+    db::list_profiles(&state.db)
+        .await
+        .map(Json)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+pub async fn create_profile(
+    State(state): State<AppState>,
+    Json(profile): Json<ImportProfile>,
+) -> Result<(StatusCode, Json<ImportProfile>), StatusCode> {
+    if !valid_profile(&profile) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    match db::get_profile(&state.db, &profile.name).await {
+        Ok(Some(_)) => return Err(StatusCode::CONFLICT),
+        Ok(None) => (),
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+
+    db::insert_profile(&state.db, &profile)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok((StatusCode::CREATED, Json(profile)))
+}
+
+pub async fn update_profile(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(mut profile): Json<ImportProfile>,
+) -> Result<Json<ImportProfile>, StatusCode> {
+    // Имя профиля берется из пути
+    profile.name = name;
+
+    if !valid_profile(&profile) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    match db::update_profile(&state.db, &profile).await {
+        Ok(true) => Ok(Json(profile)),
+        Ok(false) => Err(StatusCode::NOT_FOUND),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+pub async fn delete_profile(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> StatusCode {
+    match db::delete_profile(&state.db, &name).await {
+        Ok(true) => StatusCode::NO_CONTENT,
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
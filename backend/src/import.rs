@@ -0,0 +1,955 @@
+use calamine::{open_workbook_auto_from_rs, Reader};
+use csv::{ReaderBuilder, StringRecord, WriterBuilder};
+use encoding_rs::{UTF_16BE, UTF_16LE, WINDOWS_1251};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Cursor;
+use crate::db::NewApplicant;
+use crate::models::{is_subject_code, CsvApplicant, ImportInspection, ImportProfile, RowError};
+
+pub const MAX_SCORE: i32 = 100;
+
+// Кандидаты в разделители для CSV-выгрузок
+const DELIMITERS: [u8; 4] = [b',', b';', b'\t', b'|'];
+
+/// Загруженный файл, приведенный к тексту в UTF-8 с известным разделителем.
+pub struct SourceTable {
+    pub data: Vec<u8>,
+    pub delimiter: u8,
+}
+
+/// Определяет формат файла (xlsx/xls/ods или текст), кодировку и разделитель.
+/// `sheet` — имя листа или его номер с 1; по умолчанию первый лист.
+pub fn read_source(data: &[u8], file_name: Option<&str>, sheet: Option<&str>) -> Result<SourceTable, String> {
+    if is_spreadsheet(data, file_name) {
+        return read_workbook(data, sheet);
+    }
+
+    let text = decode_text(data);
+    let delimiter = sniff_delimiter(&text);
+
+    Ok(SourceTable { data: text.into_bytes(), delimiter })
+}
+
+fn is_spreadsheet(data: &[u8], file_name: Option<&str>) -> bool {
+    // ZIP (xlsx, ods) или OLE2 (xls)
+    if data.starts_with(b"PK\x03\x04") || data.starts_with(&[0xD0, 0xCF, 0x11, 0xE0]) {
+        return true;
+    }
+
+    let ext = file_name
+        .and_then(|n| n.rsplit_once('.'))
+        .map(|(_, ext)| ext.to_lowercase());
+    matches!(ext.as_deref(), Some("xlsx" | "xlsm" | "xls" | "xlsb" | "ods"))
+}
+
+fn read_workbook(data: &[u8], sheet: Option<&str>) -> Result<SourceTable, String> {
+    let mut workbook = open_workbook_auto_from_rs(Cursor::new(data))
+        .map_err(|e| format!("Не удалось открыть книгу: {}", e))?;
+
+    let names = workbook.sheet_names();
+    let name = match sheet.map(str::trim).filter(|s| !s.is_empty()) {
+        None => names.first().cloned(),
+        Some(wanted) => names.iter().find(|n| n.as_str() == wanted).cloned().or_else(|| {
+            wanted
+                .parse::<usize>()
+                .ok()
+                .and_then(|i| i.checked_sub(1))
+                .and_then(|i| names.get(i).cloned())
+        }),
+    };
+    let name = name.ok_or_else(|| format!("Лист не найден, доступны: {}", names.join(", ")))?;
+
+    let range = workbook
+        .worksheet_range(&name)
+        .map_err(|e| format!("Не удалось прочитать лист '{}': {}", name, e))?;
+
+    let mut writer = WriterBuilder::new().flexible(true).from_writer(Vec::new());
+    for row in range.rows() {
+        // Пустые строки в конце листа пропускаем
+        if row.iter().all(|c| c.to_string().trim().is_empty()) {
+            continue;
+        }
+        writer
+            .write_record(row.iter().map(|c| c.to_string()))
+            .map_err(|e| e.to_string())?;
+    }
+    let data = writer.into_inner().map_err(|e| e.to_string())?;
+
+    Ok(SourceTable { data, delimiter: b',' })
+}
+
+/// UTF-8 (с BOM или без), UTF-16 с BOM, иначе Windows-1251.
+fn decode_text(data: &[u8]) -> String {
+    if let Some(rest) = data.strip_prefix(&[0xEF, 0xBB, 0xBF]) {
+        return String::from_utf8_lossy(rest).into_owned();
+    }
+    if data.starts_with(&[0xFF, 0xFE]) {
+        return UTF_16LE.decode_with_bom_removal(data).0.into_owned();
+    }
+    if data.starts_with(&[0xFE, 0xFF]) {
+        return UTF_16BE.decode_with_bom_removal(data).0.into_owned();
+    }
+
+    match std::str::from_utf8(data) {
+        Ok(text) => text.to_string(),
+        Err(_) => WINDOWS_1251.decode_without_bom_handling(data).0.into_owned(),
+    }
+}
+
+/// Разделитель, который чаще всего встречается в заголовке вне кавычек.
+fn sniff_delimiter(text: &str) -> u8 {
+    let header = text.lines().find(|l| !l.trim().is_empty()).unwrap_or("");
+
+    let mut counts = [0usize; DELIMITERS.len()];
+    let mut in_quotes = false;
+    for b in header.bytes() {
+        if b == b'"' {
+            in_quotes = !in_quotes;
+        } else if !in_quotes && let Some(i) = DELIMITERS.iter().position(|&d| d == b) {
+            counts[i] += 1;
+        }
+    }
+
+    let best = (0..DELIMITERS.len()).max_by_key(|&i| (counts[i], std::cmp::Reverse(i))).unwrap_or(0);
+    if counts[best] == 0 { b',' } else { DELIMITERS[best] }
+}
+
+/// Результат разбора файла: валидные строки и ошибки по отклоненным.
+pub struct ParsedFile {
+    pub applicants: Vec<NewApplicant>,
+    pub errors: Vec<RowError>,
+    // id из отклоненных строк, если его удалось прочитать
+    pub rejected_ids: Vec<i32>,
+}
+
+/// Поля строки абитуриента, на которые сопоставляются колонки файла.
+/// Кроме них в профиле колонку можно сопоставить с кодом предмета (`math`, `inf`, ...).
+pub const FIELDS: [&str; 5] = ["id", "name", "achieve", "agreed", "priorities"];
+
+// Необязательные поля: без колонки значение пустое
+const OPTIONAL_FIELDS: [&str; 4] = ["quota", "bvi", "funding", "contract_agreed"];
+
+// Заголовки, которые распознаются без профиля (сравнение без учета регистра)
+const DEFAULT_ALIASES: [(&str, &[&str]); 9] = [
+    ("id", &["id", "Уникальный идентификатор абитуриента (ID)"]),
+    ("name", &["name", "ФИО", "Full Name"]),
+    ("achieve", &["achieve", "Балл за индивидуальные достижения", "ИД"]),
+    ("agreed", &["agreed", "Наличие согласия о зачислении в ВУЗе", "Согласие"]),
+    ("priorities", &["priorities", "Приоритет ОП", "Приоритеты"]),
+    ("quota", &["quota", "Квота", "Категория приема"]),
+    ("bvi", &["bvi", "БВИ", "Без вступительных испытаний", "Без ВИ"]),
+    ("funding", &["funding", "Основание поступления", "Основа обучения", "Вид финансирования"]),
+    ("contract_agreed", &["contract_agreed", "Согласие на платное", "Согласие по договору"]),
+];
+
+// Стандартные заголовки колонок с баллами по предметам
+const SUBJECT_ALIASES: [(&str, &[&str]); 4] = [
+    ("math", &["math", "Балл Математика", "Математика"]),
+    ("rus", &["rus", "Балл Русский язык", "Русский язык"]),
+    ("phys", &["phys", "Балл Физика/ИКТ", "Балл Физика", "Физика"]),
+    ("inf", &["inf", "Балл Информатика", "Информатика", "Информатика и ИКТ"]),
+];
+
+// Фрагменты заголовков для подсказки сопоставления. Порядок важен:
+// "идентификатор" проверяется раньше, чем "достиж"
+const SUGGEST_KEYWORDS: [(&str, &[&str]); 13] = [
+    ("id", &["идентификатор", "номер", "снилс", "код абитуриента"]),
+    ("name", &["фио", "фамилия", "name"]),
+    ("math", &["матем", "math"]),
+    ("rus", &["русск", "rus"]),
+    ("phys", &["физ", "phys"]),
+    ("inf", &["информ", "икт", "inf"]),
+    ("achieve", &["достиж", "achieve"]),
+    ("contract_agreed", &["согласие на плат", "согласие по договор", "contract consent"]),
+    ("agreed", &["согл", "agree", "consent"]),
+    ("priorities", &["приорит", "направлен", "priorit"]),
+    ("quota", &["квот", "категор", "quota"]),
+    ("bvi", &["бви", "без вступ", "олимпиад", "bvi"]),
+    ("funding", &["основание", "финансир", "основа обучения", "funding"]),
+];
+
+// Значения колонки квоты (без учета регистра); пустое значение — общий конкурс
+const QUOTA_VALUES: [(&str, &[&str]); 3] = [
+    ("special", &["special", "особая", "особая квота", "особое право"]),
+    ("target", &["target", "целевая", "целевая квота", "целевое"]),
+    ("separate", &["separate", "отдельная", "отдельная квота"]),
+];
+
+// Значения колонки оснований (без учета регистра), через `;`, `,` или `/`;
+// пустое значение — только бюджет
+const FUNDING_VALUES: [(&str, &[&str]); 2] = [
+    ("budget", &["budget", "бюджет", "бюджетная основа", "б"]),
+    ("contract", &["contract", "договор", "платное", "платная основа", "контракт", "п"]),
+];
+
+const TRUE_VALUES: [&str; 5] = ["true", "1", "да", "+", "yes"];
+const FALSE_VALUES: [&str; 6] = ["false", "0", "нет", "-", "no", ""];
+
+fn normalize(header: &str) -> String {
+    header.trim().trim_start_matches('\u{feff}').to_lowercase()
+}
+
+/// Допустимая цель сопоставления в профиле: поле строки или код предмета.
+pub fn is_mapping_target(field: &str) -> bool {
+    FIELDS.contains(&field) || OPTIONAL_FIELDS.contains(&field) || is_subject_code(field)
+}
+
+/// Номера колонок файла для каждого поля и для каждого предмета.
+struct ColumnMap {
+    index: HashMap<&'static str, usize>,
+    subjects: Vec<(String, usize)>,
+}
+
+impl ColumnMap {
+    /// Сначала колонки из профиля, затем стандартные заголовки для оставшихся полей.
+    fn resolve(headers: &StringRecord, profile: Option<&ImportProfile>) -> Self {
+        let mut map = ColumnMap { index: HashMap::new(), subjects: Vec::new() };
+        let mut used: HashSet<usize> = HashSet::new();
+
+        if let Some(profile) = profile {
+            let columns: HashMap<String, &str> = profile
+                .columns
+                .iter()
+                .map(|(header, field)| (normalize(header), field.as_str()))
+                .collect();
+
+            for (i, header) in headers.iter().enumerate() {
+                if let Some(field) = columns.get(&normalize(header)) {
+                    map.assign(field, i, &mut used);
+                }
+            }
+        }
+
+        for (i, header) in headers.iter().enumerate() {
+            let header = normalize(header);
+            for (field, aliases) in DEFAULT_ALIASES.iter().chain(SUBJECT_ALIASES.iter()) {
+                if aliases.iter().any(|a| normalize(a) == header) {
+                    map.assign(field, i, &mut used);
+                }
+            }
+        }
+
+        map
+    }
+
+    /// Закрепляет колонку `i` за полем или предметом, если ни то, ни другое еще не занято.
+    fn assign(&mut self, field: &str, i: usize, used: &mut HashSet<usize>) {
+        if used.contains(&i) {
+            return;
+        }
+
+        if let Some(field) = FIELDS.iter().chain(OPTIONAL_FIELDS.iter()).find(|f| **f == field) {
+            if self.index.contains_key(field) {
+                return;
+            }
+            self.index.insert(field, i);
+        } else if is_subject_code(field) {
+            if self.subjects.iter().any(|(s, _)| s == field) {
+                return;
+            }
+            self.subjects.push((field.to_string(), i));
+        } else {
+            return;
+        }
+
+        used.insert(i);
+    }
+
+    /// Ненайденные поля из `required`; `subjects` — если нет ни одной колонки с баллами.
+    fn missing(&self, required: &[&'static str]) -> Vec<&'static str> {
+        let mut missing: Vec<&'static str> = required.iter().copied().filter(|f| !self.index.contains_key(f)).collect();
+        if self.subjects.is_empty() {
+            missing.push("subjects");
+        }
+        missing
+    }
+
+    fn get<'r>(&self, record: &'r StringRecord, field: &str) -> &'r str {
+        self.index.get(field).and_then(|&i| record.get(i)).unwrap_or("")
+    }
+
+    fn extract(&self, record: &StringRecord) -> CsvApplicant {
+        CsvApplicant {
+            id: self.get(record, "id").to_string(),
+            name: self.get(record, "name").to_string(),
+            scores: self
+                .subjects
+                .iter()
+                .map(|(subject, i)| (subject.clone(), record.get(*i).unwrap_or("").to_string()))
+                .collect(),
+            achieve: self.get(record, "achieve").to_string(),
+            agreed: self.get(record, "agreed").to_string(),
+            priorities: self.get(record, "priorities").to_string(),
+            quota: self.get(record, "quota").to_string(),
+            bvi: self.get(record, "bvi").to_string(),
+            funding: self.get(record, "funding").to_string(),
+            contract_agreed: self.get(record, "contract_agreed").to_string(),
+        }
+    }
+}
+
+/// Настройки значений из профиля (или стандартные).
+struct ValueRules<'a> {
+    profile: Option<&'a ImportProfile>,
+}
+
+impl ValueRules<'_> {
+    fn agreed(&self, value: &str) -> Option<bool> {
+        let value = value.trim().to_lowercase();
+
+        if let Some(p) = self.profile {
+            if p.agreed_true.iter().any(|v| v.trim().to_lowercase() == value) {
+                return Some(true);
+            }
+            if p.agreed_false.iter().any(|v| v.trim().to_lowercase() == value) {
+                return Some(false);
+            }
+        }
+
+        parse_flag(&value)
+    }
+
+    fn priorities(&self, value: &str) -> Vec<String> {
+        let parts: Vec<&str> = match self.profile.and_then(|p| p.priority_separator.as_deref()) {
+            Some(sep) if !sep.is_empty() => value.split(sep).collect(),
+            // В выгрузках с разделителем ';' приоритеты обычно перечислены через запятую
+            _ => value.split([';', ',']).collect(),
+        };
+
+        parts
+            .into_iter()
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect()
+    }
+}
+
+fn open_reader(source: &SourceTable) -> csv::Reader<&[u8]> {
+    ReaderBuilder::new()
+        .delimiter(source.delimiter)
+        .trim(csv::Trim::Headers)
+        .flexible(true)
+        .from_reader(&source.data[..])
+}
+
+/// Разбирает таблицу и проверяет каждую строку. Строки с ошибками не попадают в `applicants`.
+/// `known_programs` — коды из справочника направлений. Если в заголовке не нашлось
+/// нужных колонок, весь файл отклоняется.
+pub fn parse_csv(
+    source: &SourceTable,
+    known_programs: &HashSet<String>,
+    profile: Option<&ImportProfile>,
+) -> Result<ParsedFile, String> {
+    let mut parsed = ParsedFile { applicants: Vec::new(), errors: Vec::new(), rejected_ids: Vec::new() };
+    let mut rdr = open_reader(source);
+
+    let headers = rdr
+        .headers()
+        .map_err(|e| format!("Не удалось прочитать заголовок: {}", e))?
+        .clone();
+
+    let columns = ColumnMap::resolve(&headers, profile);
+    let missing = columns.missing(&FIELDS);
+    if !missing.is_empty() {
+        return Err(format!("Не найдены колонки: {}", missing.join(", ")));
+    }
+
+    let rules = ValueRules { profile };
+
+    // id -> строка, где он встретился впервые
+    let mut seen_ids: HashMap<i32, u64> = HashMap::new();
+
+    for result in rdr.records() {
+        let record = match result {
+            Ok(r) => r,
+            Err(e) => {
+                let line = e.position().map_or(0, |p| p.line());
+                parsed.errors.push(RowError::new(line, None, e.to_string()));
+                continue;
+            }
+        };
+
+        let line = record.position().map_or(0, |p| p.line());
+        let raw_id = columns.get(&record, "id").trim().parse::<i32>().ok();
+
+        if record.len() != headers.len() {
+            parsed.rejected_ids.extend(raw_id);
+            parsed.errors.push(RowError::new(
+                line,
+                None,
+                format!("Ожидалось колонок: {}, в строке: {}", headers.len(), record.len()),
+            ));
+            continue;
+        }
+
+        match validate_record(columns.extract(&record), &rules, line, known_programs) {
+            Ok(applicant) => {
+                if let Some(first) = seen_ids.get(&applicant.external_id) {
+                    parsed.errors.push(RowError::new(
+                        line,
+                        Some("id"),
+                        format!("Дубликат id {} (впервые в строке {})", applicant.external_id, first),
+                    ));
+                    continue;
+                }
+                seen_ids.insert(applicant.external_id, line);
+                parsed.applicants.push(applicant);
+            }
+            Err(mut errors) => {
+                parsed.rejected_ids.extend(raw_id);
+                parsed.errors.append(&mut errors);
+            }
+        }
+    }
+
+    Ok(parsed)
+}
+
+/// Разбор файла для режима удаления: из каждой строки берется только id.
+/// Остальные поля заполняются пустыми значениями.
+pub fn parse_ids(source: &SourceTable, profile: Option<&ImportProfile>) -> Result<ParsedFile, String> {
+    let mut parsed = ParsedFile { applicants: Vec::new(), errors: Vec::new(), rejected_ids: Vec::new() };
+    let mut rdr = open_reader(source);
+
+    let headers = rdr
+        .headers()
+        .map_err(|e| format!("Не удалось прочитать заголовок: {}", e))?
+        .clone();
+
+    let columns = ColumnMap::resolve(&headers, profile);
+    if !columns.index.contains_key("id") {
+        return Err("Не найдена колонка: id".to_string());
+    }
+
+    let mut seen_ids: HashSet<i32> = HashSet::new();
+
+    for result in rdr.records() {
+        let record = match result {
+            Ok(r) => r,
+            Err(e) => {
+                let line = e.position().map_or(0, |p| p.line());
+                parsed.errors.push(RowError::new(line, None, e.to_string()));
+                continue;
+            }
+        };
+
+        let line = record.position().map_or(0, |p| p.line());
+        let raw_id = columns.get(&record, "id");
+
+        match raw_id.trim().parse::<i32>() {
+            Ok(id) if seen_ids.insert(id) => parsed.applicants.push(NewApplicant {
+                external_id: id,
+                full_name: String::new(),
+                scores: BTreeMap::new(),
+                score_achieve: 0,
+                agreed: false,
+                budget: true,
+                contract: false,
+                contract_agreed: false,
+                bvi: false,
+                priorities: Vec::new(),
+                quota: None,
+            }),
+            Ok(_) => (),
+            Err(_) => parsed.errors.push(RowError::new(line, Some("id"), format!("id не является числом: '{}'", raw_id))),
+        }
+    }
+
+    Ok(parsed)
+}
+
+/// Заголовки файла, предлагаемое сопоставление колонок и первые строки.
+pub fn inspect(source: &SourceTable, profile: Option<&ImportProfile>) -> Result<ImportInspection, String> {
+    let mut rdr = open_reader(source);
+
+    let headers = rdr
+        .headers()
+        .map_err(|e| format!("Не удалось прочитать заголовок: {}", e))?
+        .clone();
+
+    let columns = ColumnMap::resolve(&headers, profile);
+    let mut suggested: BTreeMap<String, String> = BTreeMap::new();
+    let mut assigned: HashSet<&str> = HashSet::new();
+
+    for (field, &i) in &columns.index {
+        suggested.insert(headers[i].to_string(), field.to_string());
+        assigned.insert(field);
+    }
+    for (subject, i) in &columns.subjects {
+        suggested.insert(headers[*i].to_string(), subject.clone());
+        assigned.insert(subject);
+    }
+
+    // Для оставшихся заголовков — поиск по фрагментам
+    for header in headers.iter() {
+        if suggested.contains_key(header) {
+            continue;
+        }
+        let normalized = normalize(header);
+        let guess = SUGGEST_KEYWORDS.iter().find(|(field, keywords)| {
+            !assigned.contains(field) && keywords.iter().any(|k| normalized.contains(k))
+        });
+        if let Some((field, _)) = guess {
+            suggested.insert(header.to_string(), field.to_string());
+            assigned.insert(field);
+        }
+    }
+
+    let mut missing_fields: Vec<String> = FIELDS
+        .iter()
+        .filter(|f| !assigned.contains(*f))
+        .map(|f| f.to_string())
+        .collect();
+    if !suggested.values().any(|f| !FIELDS.contains(&f.as_str())) {
+        missing_fields.push("subjects".to_string());
+    }
+
+    let sample = rdr
+        .records()
+        .filter_map(Result::ok)
+        .take(5)
+        .map(|r| r.iter().map(|v| v.to_string()).collect())
+        .collect();
+
+    let delimiter = match source.delimiter {
+        b'\t' => "\\t".to_string(),
+        d => (d as char).to_string(),
+    };
+
+    Ok(ImportInspection {
+        delimiter,
+        headers: headers.iter().map(|h| h.to_string()).collect(),
+        suggested,
+        missing_fields,
+        sample,
+    })
+}
+
+fn parse_score(value: &str, line: u64, column: &str, errors: &mut Vec<RowError>) -> Option<i32> {
+    match value.trim().parse::<i32>() {
+        Ok(v) if (0..=MAX_SCORE).contains(&v) => Some(v),
+        Ok(v) => {
+            errors.push(RowError::new(line, Some(column), format!("Балл {} вне диапазона 0..{}", v, MAX_SCORE)));
+            None
+        }
+        Err(_) => {
+            errors.push(RowError::new(line, Some(column), format!("Балл не является числом: '{}'", value)));
+            None
+        }
+    }
+}
+
+fn validate_record(
+    raw: CsvApplicant,
+    rules: &ValueRules,
+    line: u64,
+    known_programs: &HashSet<String>,
+) -> Result<NewApplicant, Vec<RowError>> {
+    let mut errors = Vec::new();
+
+    let external_id = match raw.id.trim().parse::<i32>() {
+        Ok(id) => id,
+        Err(_) => {
+            errors.push(RowError::new(line, Some("id"), format!("id не является числом: '{}'", raw.id)));
+            0
+        }
+    };
+
+    let full_name = raw.name.trim().to_string();
+    if full_name.is_empty() {
+        errors.push(RowError::new(line, Some("name"), "Пустое ФИО".to_string()));
+    }
+
+    let mut scores = BTreeMap::new();
+    for (subject, value) in &raw.scores {
+        // Пустая ячейка — предмет не сдавался
+        if value.trim().is_empty() {
+            continue;
+        }
+        if let Some(score) = parse_score(value, line, subject, &mut errors) {
+            scores.insert(subject.clone(), score);
+        }
+    }
+
+    let score_achieve = parse_score(&raw.achieve, line, "achieve", &mut errors).unwrap_or(0);
+
+    let agreed = match rules.agreed(&raw.agreed) {
+        Some(v) => v,
+        None => {
+            errors.push(RowError::new(line, Some("agreed"), format!("Нераспознанное значение согласия: '{}'", raw.agreed)));
+            false
+        }
+    };
+
+    let priorities = rules.priorities(&raw.priorities);
+
+    // Без колонки БВИ значение пустое — обычный конкурс
+    let bvi = match parse_flag(&raw.bvi) {
+        Some(v) => v,
+        None => {
+            errors.push(RowError::new(line, Some("bvi"), format!("Нераспознанное значение БВИ: '{}'", raw.bvi)));
+            false
+        }
+    };
+
+    let quota = match parse_quota(&raw.quota) {
+        Ok(q) => q,
+        Err(()) => {
+            errors.push(RowError::new(line, Some("quota"), format!("Неизвестная категория квоты: '{}'", raw.quota)));
+            None
+        }
+    };
+
+    let (budget, contract) = match parse_funding(&raw.funding) {
+        Some(bases) => bases,
+        None => {
+            errors.push(RowError::new(line, Some("funding"), format!("Нераспознанное основание поступления: '{}'", raw.funding)));
+            (true, false)
+        }
+    };
+
+    let contract_agreed = match parse_flag(&raw.contract_agreed) {
+        Some(v) => v,
+        None => {
+            errors.push(RowError::new(
+                line,
+                Some("contract_agreed"),
+                format!("Нераспознанное значение согласия по договору: '{}'", raw.contract_agreed),
+            ));
+            false
+        }
+    };
+
+    for code in &priorities {
+        if !known_programs.contains(code) {
+            errors.push(RowError::new(line, Some("priorities"), format!("Неизвестный код направления: '{}'", code)));
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    Ok(NewApplicant {
+        external_id,
+        full_name,
+        scores,
+        score_achieve,
+        agreed,
+        budget,
+        contract,
+        contract_agreed,
+        bvi,
+        priorities,
+        quota,
+    })
+}
+
+fn parse_flag(value: &str) -> Option<bool> {
+    let value = value.trim().to_lowercase();
+    if TRUE_VALUES.contains(&value.as_str()) {
+        Some(true)
+    } else if FALSE_VALUES.contains(&value.as_str()) {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// Основания поступления `(бюджет, договор)`; `None` — нераспознанное значение.
+fn parse_funding(value: &str) -> Option<(bool, bool)> {
+    let value = value.trim().to_lowercase();
+    if value.is_empty() {
+        return Some((true, false));
+    }
+
+    let mut bases = (false, false);
+    for part in value.split([';', ',', '/']).map(str::trim).filter(|p| !p.is_empty()) {
+        match FUNDING_VALUES.iter().find(|(_, names)| names.contains(&part)) {
+            Some(("budget", _)) => bases.0 = true,
+            Some(_) => bases.1 = true,
+            None => return None,
+        }
+    }
+    Some(bases)
+}
+
+fn parse_quota(value: &str) -> Result<Option<String>, ()> {
+    let value = value.trim().to_lowercase();
+    if value.is_empty() || value == "-" {
+        return Ok(None);
+    }
+
+    QUOTA_VALUES
+        .iter()
+        .find(|(_, names)| names.contains(&value.as_str()))
+        .map(|(code, _)| Some(code.to_string()))
+        .ok_or(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+
+    fn table(text: &str) -> SourceTable {
+        SourceTable { data: text.as_bytes().to_vec(), delimiter: sniff_delimiter(text) }
+    }
+
+    fn programs() -> HashSet<String> {
+        ["ПМ", "ИВТ"].map(String::from).into_iter().collect()
+    }
+
+    /// Книга xlsx из листов `(имя, строки)`; ячейки записываются строками.
+    fn workbook(sheets: &[(&str, &[&[&str]])]) -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        let mut file = |name: &str, content: String| {
+            zip.start_file(name, options).unwrap();
+            zip.write_all(content.as_bytes()).unwrap();
+        };
+
+        let overrides: String = (1..=sheets.len())
+            .map(|i| format!(r#"<Override PartName="/xl/worksheets/sheet{i}.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml"/>"#))
+            .collect();
+        file("[Content_Types].xml", format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?><Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types"><Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/><Default Extension="xml" ContentType="application/xml"/><Override PartName="/xl/workbook.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml"/>{overrides}</Types>"#
+        ));
+        file("_rels/.rels", r#"<?xml version="1.0" encoding="UTF-8"?><Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="xl/workbook.xml"/></Relationships>"#.to_string());
+
+        let entries: String = sheets
+            .iter()
+            .enumerate()
+            .map(|(i, (name, _))| format!(r#"<sheet name="{name}" sheetId="{}" r:id="rId{}"/>"#, i + 1, i + 1))
+            .collect();
+        file("xl/workbook.xml", format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?><workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships"><sheets>{entries}</sheets></workbook>"#
+        ));
+        let rels: String = (1..=sheets.len())
+            .map(|i| format!(r#"<Relationship Id="rId{i}" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet{i}.xml"/>"#))
+            .collect();
+        file("xl/_rels/workbook.xml.rels", format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?><Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">{rels}</Relationships>"#
+        ));
+
+        for (i, (_, rows)) in sheets.iter().enumerate() {
+            let data: String = rows
+                .iter()
+                .enumerate()
+                .map(|(r, cells)| {
+                    let cells: String = cells
+                        .iter()
+                        .enumerate()
+                        .map(|(c, v)| format!(r#"<c r="{}{}" t="inlineStr"><is><t>{v}</t></is></c>"#, (b'A' + c as u8) as char, r + 1))
+                        .collect();
+                    format!(r#"<row r="{}">{cells}</row>"#, r + 1)
+                })
+                .collect();
+            file(&format!("xl/worksheets/sheet{}.xml", i + 1), format!(
+                r#"<?xml version="1.0" encoding="UTF-8"?><worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"><sheetData>{data}</sheetData></worksheet>"#
+            ));
+        }
+
+        zip.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn decodes_text_encodings() {
+        let cp1251 = WINDOWS_1251.encode("ФИО;Согласие").0.into_owned();
+        let utf16: Vec<u8> = [0xFF, 0xFE].into_iter().chain("id,ФИО".encode_utf16().flat_map(u16::to_le_bytes)).collect();
+        let cases: [(&[u8], &str); 4] = [
+            ("id,ФИО".as_bytes(), "id,ФИО"),
+            (b"\xEF\xBB\xBFid,name", "id,name"),
+            (&cp1251, "ФИО;Согласие"),
+            (&utf16, "id,ФИО"),
+        ];
+        for (data, expected) in cases {
+            assert_eq!(decode_text(data), expected);
+        }
+    }
+
+    #[test]
+    fn sniffs_delimiter_from_header() {
+        let cases = [
+            ("id,name,math\n1,a,2", b','),
+            ("id;name;math\n1,5;a;2", b';'),
+            ("id\tname\tmath", b'\t'),
+            ("id|name|math", b'|'),
+            // Запятые внутри кавычек не считаются
+            ("\"id,1\";\"name,2\";math", b';'),
+            ("\n\nid;name", b';'),
+            ("single", b','),
+            ("", b','),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(sniff_delimiter(text) as char, expected as char, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn reads_workbook_sheet_by_name_or_number() {
+        let first: &[&[&str]] = &[&["id", "ФИО"], &["1", "Иванов"], &["", ""]];
+        let second: &[&[&str]] = &[&["id"], &["2"]];
+        let data = workbook(&[("Список", first), ("Доп", second)]);
+        assert!(is_spreadsheet(&data, None));
+        assert!(is_spreadsheet(b"id,name", Some("list.XLSX")));
+        assert!(!is_spreadsheet(b"id,name", Some("list.csv")));
+
+        let text = |sheet: Option<&str>| String::from_utf8(read_source(&data, None, sheet).unwrap().data).unwrap();
+        // Пустые строки в конце листа пропускаются
+        assert_eq!(text(None), "id,ФИО\n1,Иванов\n");
+        assert_eq!(text(Some("Доп")), "id\n2\n");
+        assert_eq!(text(Some("2")), "id\n2\n");
+
+        let missing = read_source(&data, None, Some("3")).err().unwrap();
+        assert_eq!(missing, "Лист не найден, доступны: Список, Доп");
+    }
+
+    #[test]
+    fn rejects_rows_with_reasons() {
+        let header = "id,name,math,rus,achieve,agreed,priorities,quota,bvi,funding";
+        let cases = [
+            ("x,Иванов,80,70,0,да,ПМ,,,", "id", "id не является числом: 'x'"),
+            ("1, ,80,70,0,да,ПМ,,,", "name", "Пустое ФИО"),
+            ("1,Иванов,101,70,0,да,ПМ,,,", "math", "Балл 101 вне диапазона 0..100"),
+            ("1,Иванов,80,abc,0,да,ПМ,,,", "rus", "Балл не является числом: 'abc'"),
+            ("1,Иванов,80,70,0,может,ПМ,,,", "agreed", "Нераспознанное значение согласия: 'может'"),
+            ("1,Иванов,80,70,0,да,ФИЗ,,,", "priorities", "Неизвестный код направления: 'ФИЗ'"),
+            ("1,Иванов,80,70,0,да,ПМ,льготная,,", "quota", "Неизвестная категория квоты: 'льготная'"),
+            ("1,Иванов,80,70,0,да,ПМ,,может,", "bvi", "Нераспознанное значение БВИ: 'может'"),
+            ("1,Иванов,80,70,0,да,ПМ,,,грант", "funding", "Нераспознанное основание поступления: 'грант'"),
+        ];
+        for (row, column, reason) in cases {
+            let parsed = parse_csv(&table(&format!("{header}\n{row}")), &programs(), None).unwrap();
+            assert!(parsed.applicants.is_empty(), "{}", row);
+            assert_eq!(parsed.errors.len(), 1, "{}", row);
+            assert_eq!((parsed.errors[0].line, parsed.errors[0].column.as_deref()), (2, Some(column)));
+            assert_eq!(parsed.errors[0].reason, reason);
+        }
+    }
+
+    #[test]
+    fn parses_valid_rows_and_reports_duplicates() {
+        let text = "id;ФИО;Математика;ИД;Согласие;Приоритеты;Квота;Основание поступления\n\
+                    1;Иванов;80;5;да;ПМ,ИВТ;целевая;бюджет/договор\n\
+                    2;Петров;;0;нет;ИВТ;;\n\
+                    1;Сидоров;90;0;да;ПМ;;\n\
+                    3;Козлов;70;0;да\n";
+        let parsed = parse_csv(&table(text), &programs(), None).unwrap();
+
+        let first = &parsed.applicants[0];
+        assert_eq!((first.external_id, first.full_name.as_str(), first.score_achieve), (1, "Иванов", 5));
+        assert_eq!(first.scores, BTreeMap::from([("math".to_string(), 80)]));
+        assert_eq!(first.priorities, vec!["ПМ", "ИВТ"]);
+        assert_eq!(first.quota.as_deref(), Some("target"));
+        assert!(first.agreed && first.budget && first.contract);
+        // Пустая ячейка — предмет не сдавался
+        assert!(parsed.applicants[1].scores.is_empty());
+        assert_eq!(parsed.applicants.len(), 2);
+
+        let errors: Vec<_> = parsed.errors.iter().map(|e| (e.line, e.reason.as_str())).collect();
+        assert_eq!(
+            errors,
+            vec![(4, "Дубликат id 1 (впервые в строке 2)"), (5, "Ожидалось колонок: 8, в строке: 5")]
+        );
+        assert_eq!(parsed.rejected_ids, vec![3]);
+
+        let missing = parse_csv(&table("id,name,agreed\n1,a,да"), &programs(), None).err().unwrap();
+        assert_eq!(missing, "Не найдены колонки: achieve, priorities, subjects");
+    }
+
+    #[test]
+    fn profile_columns_take_precedence() {
+        let profile = ImportProfile {
+            name: "vuz".to_string(),
+            columns: BTreeMap::from([
+                ("Номер".to_string(), "id".to_string()),
+                ("Балл ЕГЭ (инф)".to_string(), "inf".to_string()),
+                ("ФИО".to_string(), "name".to_string()),
+            ]),
+            agreed_true: vec!["подано".to_string()],
+            agreed_false: vec!["не подано".to_string()],
+            priority_separator: Some("|".to_string()),
+        };
+        let text = "Номер,ФИО,Балл ЕГЭ (инф),ИД,Согласие,Приоритеты\n7,Иванов,88,0,подано,ИВТ|ПМ";
+        let parsed = parse_csv(&table(text), &programs(), Some(&profile)).unwrap();
+
+        assert!(parsed.errors.is_empty());
+        let applicant = &parsed.applicants[0];
+        assert_eq!(applicant.external_id, 7);
+        assert_eq!(applicant.scores, BTreeMap::from([("inf".to_string(), 88)]));
+        assert!(applicant.agreed);
+        assert_eq!(applicant.priorities, vec!["ИВТ", "ПМ"]);
+    }
+
+    #[test]
+    fn suggests_columns_by_header_fragments() {
+        let text = "Уникальный идентификатор абитуриента (ID);Фамилия Имя Отчество;Результат по математике;\
+                    Достижения;Согласие на платное обучение;Согласие на зачисление;Выбранные направления;Примечание\n\
+                    1;Иванов;80;0;нет;да;ПМ;-";
+        let inspection = inspect(&table(text), None).unwrap();
+
+        assert_eq!(inspection.delimiter, ";");
+        let suggested: Vec<(&str, &str)> = inspection
+            .suggested
+            .iter()
+            .map(|(header, field)| (header.as_str(), field.as_str()))
+            .collect();
+        assert_eq!(
+            suggested,
+            vec![
+                ("Выбранные направления", "priorities"),
+                ("Достижения", "achieve"),
+                ("Результат по математике", "math"),
+                ("Согласие на зачисление", "agreed"),
+                ("Согласие на платное обучение", "contract_agreed"),
+                ("Уникальный идентификатор абитуриента (ID)", "id"),
+                ("Фамилия Имя Отчество", "name"),
+            ]
+        );
+        assert!(inspection.missing_fields.is_empty());
+        assert_eq!(inspection.sample.len(), 1);
+
+        let bare = inspect(&table("Номер,Комментарий"), None).unwrap();
+        assert_eq!(bare.missing_fields, vec!["name", "achieve", "agreed", "priorities", "subjects"]);
+    }
+
+    #[test]
+    fn parses_flags_quotas_and_funding() {
+        let flags = [
+            ("да", Some(true)),
+            (" YES ", Some(true)),
+            ("+", Some(true)),
+            ("нет", Some(false)),
+            ("", Some(false)),
+            ("возможно", None),
+        ];
+        for (value, expected) in flags {
+            assert_eq!(parse_flag(value), expected, "{:?}", value);
+        }
+
+        let quotas = [
+            ("", Ok(None)),
+            ("-", Ok(None)),
+            ("Особая квота", Ok(Some("special"))),
+            ("целевое", Ok(Some("target"))),
+            ("separate", Ok(Some("separate"))),
+            ("льгота", Err(())),
+        ];
+        for (value, expected) in quotas {
+            assert_eq!(parse_quota(value), expected.map(|q| q.map(String::from)), "{:?}", value);
+        }
+
+        let funding = [
+            ("", Some((true, false))),
+            ("Договор", Some((false, true))),
+            ("б/п", Some((true, true))),
+            ("бюджет; контракт", Some((true, true))),
+            ("грант", None),
+        ];
+        for (value, expected) in funding {
+            assert_eq!(parse_funding(value), expected, "{:?}", value);
+        }
+    }
+}
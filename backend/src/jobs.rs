@@ -0,0 +1,198 @@
+use serde::Serialize;
+use sqlx::SqlitePool;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::task::{JoinError, JoinHandle};
+use crate::logic;
+
+/// Сколько последних задач держим в памяти.
+const MAX_JOBS: usize = 200;
+
+#[derive(Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Done,
+    Failed,
+}
+
+#[derive(Serialize, Clone)]
+pub struct Job {
+    pub id: u64,
+    pub kind: String,
+    pub report_date: String,
+    pub status: JobStatus,
+    pub created_at: String,
+    pub started_at: Option<String>,
+    pub finished_at: Option<String>,
+    pub duration_ms: Option<u64>,
+    pub error: Option<String>,
+}
+
+/// Реестр фоновых задач пересчета. Задачи выполняются строго по одной,
+/// чтобы два импорта подряд не переписывали `current_program` одновременно.
+#[derive(Clone, Default)]
+pub struct JobRegistry {
+    jobs: Arc<Mutex<BTreeMap<u64, Job>>>,
+    run_lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl JobRegistry {
+    pub fn get(&self, id: u64) -> Option<Job> {
+        self.jobs.lock().unwrap().get(&id).cloned()
+    }
+
+    fn create(&self, kind: &str, report_date: &str) -> u64 {
+        let mut jobs = self.jobs.lock().unwrap();
+        let id = jobs.keys().next_back().map_or(1, |last| last + 1);
+
+        jobs.insert(id, Job {
+            id,
+            kind: kind.to_string(),
+            report_date: report_date.to_string(),
+            status: JobStatus::Queued,
+            created_at: now(),
+            started_at: None,
+            finished_at: None,
+            duration_ms: None,
+            error: None,
+        });
+
+        while jobs.len() > MAX_JOBS {
+            jobs.pop_first();
+        }
+
+        id
+    }
+
+    fn update(&self, id: u64, f: impl FnOnce(&mut Job)) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(&id) {
+            f(job);
+        }
+    }
+
+    /// Ставит в очередь пересчет распределения для среза.
+    /// Возвращает id задачи и хэндл, который можно дождаться через [`JobRegistry::wait`].
+    pub fn spawn_recalculation(
+        &self,
+        pool: SqlitePool,
+        snapshot_id: i64,
+        report_date: String,
+    ) -> (u64, JoinHandle<()>) {
+        let task_date = report_date.clone();
+        self.spawn("recalculate", &report_date, async move {
+            logic::recalculate_admissions(&pool, snapshot_id, &task_date)
+                .await
+                .map_err(|e| e.to_string())
+        })
+    }
+
+    /// Ставит в очередь закрытие этапа: пересчет среза и фиксацию зачисленных одной задачей.
+    pub fn spawn_stage_freeze(
+        &self,
+        pool: SqlitePool,
+        stage_code: String,
+        snapshot_id: i64,
+        report_date: String,
+    ) -> (u64, JoinHandle<()>) {
+        let task_date = report_date.clone();
+        self.spawn("freeze_stage", &report_date, async move {
+            logic::freeze_stage(&pool, &stage_code, snapshot_id, &task_date)
+                .await
+                .map_err(|e| e.to_string())
+        })
+    }
+
+    fn spawn<F>(&self, kind: &str, report_date: &str, task: F) -> (u64, JoinHandle<()>)
+    where
+        F: Future<Output = Result<(), String>> + Send + 'static,
+    {
+        let id = self.create(kind, report_date);
+        let registry = self.clone();
+
+        let handle = tokio::spawn(async move {
+            let _guard = registry.run_lock.lock().await;
+
+            let started = Instant::now();
+            registry.update(id, |job| {
+                job.status = JobStatus::Running;
+                job.started_at = Some(now());
+            });
+
+            // Задача выполняется отдельно, чтобы паника в ней не оставила статус `running`
+            let result = match tokio::spawn(task).await {
+                Ok(result) => result,
+                Err(e) => Err(join_error_message(e)),
+            };
+
+            registry.update(id, |job| {
+                job.finished_at = Some(now());
+                job.duration_ms = Some(started.elapsed().as_millis() as u64);
+                match result {
+                    Ok(()) => job.status = JobStatus::Done,
+                    Err(message) => {
+                        job.status = JobStatus::Failed;
+                        job.error = Some(message);
+                    }
+                }
+            });
+        });
+
+        (id, handle)
+    }
+
+    /// Дожидается задачи и возвращает ее итоговое состояние.
+    /// Если сама обертка задачи упала, задача помечается как `failed`.
+    pub async fn wait(&self, id: u64, handle: JoinHandle<()>) -> Option<Job> {
+        if let Err(e) = handle.await {
+            let message = join_error_message(e);
+            self.update(id, |job| {
+                if matches!(job.status, JobStatus::Queued | JobStatus::Running) {
+                    job.status = JobStatus::Failed;
+                    job.finished_at = Some(now());
+                    job.error = Some(message);
+                }
+            });
+        }
+        self.get(id)
+    }
+}
+
+fn join_error_message(e: JoinError) -> String {
+    if !e.is_panic() {
+        return e.to_string();
+    }
+    let payload = e.into_panic();
+    let detail = payload
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_default();
+    format!("Задача завершилась паникой: {}", detail)
+}
+
+fn now() -> String {
+    chrono::Local::now().to_rfc3339()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn panicking_job_is_marked_failed() {
+        let registry = JobRegistry::default();
+        let (id, handle) = registry.spawn("test", "2024-08-01", async { panic!("сбой") });
+
+        let job = registry.wait(id, handle).await.unwrap();
+        assert!(job.status == JobStatus::Failed);
+        assert!(job.error.unwrap().contains("сбой"));
+        assert!(job.finished_at.is_some());
+
+        // Блокировка освобождена: следующая задача выполняется
+        let (id, handle) = registry.spawn("test", "2024-08-01", async { Ok(()) });
+        assert!(registry.wait(id, handle).await.unwrap().status == JobStatus::Done);
+    }
+}
@@ -1,166 +1,635 @@
 use sqlx::SqlitePool;
-use std::collections::{HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 // use chrono::Local;
 use crate::db;
+use crate::db::{AdmissionRecord, NewApplicant};
+use crate::engine::{
+    allocate_staged, apply_allocation, competes_for, Admission, meets_requirements, priority_position, program_score, rank_criteria, rank_criteria_names,
+    rank_key, StagePlan,
+};
+use crate::forecast::passing_score_forecasts;
+use crate::import::MAX_SCORE;
+use crate::models::{
+    is_subject_code, Applicant, ApplicantChange, ApplicantDetail, ChoiceExplanation, ContractStats, Enrollment, EnrollmentStage, FundingBasis, HistoryPoint, ImportMode, ImportPreview, IntersectionEntry, Program, ProgramRanking,
+    PassingScoreForecast, ProgramStats, QuotaStats, RankingEntry, RankingStatus, SimulationChange, SimulationResult, SnapshotDiff, QUOTAS,
+};
 
-fn get_program_limits() -> HashMap<String, usize> {
-    let mut map = HashMap::new();
-    map.insert("ПМ".to_string(), 40);
-    map.insert("ИВТ".to_string(), 50);
-    map.insert("ИТСС".to_string(), 30);
-    map.insert("ИБ".to_string(), 20);
-    map
+/// Пересчитывает зачисление внутри среза `snapshot_id` и пишет историю за `date`.
+/// Распределение считается в памяти с учетом этапов, результат сохраняется одной транзакцией.
+/// В той же транзакции сохраняются прогнозы проходного балла за `date` на сроки этапов.
+pub async fn recalculate_admissions(pool: &SqlitePool, snapshot_id: i64, date: &str) -> Result<(), sqlx::Error> {
+    let mut applicants = db::get_all_applicants(pool, snapshot_id).await?;
+    let programs = db::list_programs(pool).await?;
+    let stages = db::list_stages(pool).await?;
+    let plan = StagePlan::at(date, &stages, &db::list_enrollments(pool).await?);
+
+    let allocation = allocate_staged(&applicants, &programs, &plan);
+
+    let mut admissions: Vec<AdmissionRecord> = Vec::new();
+    for program in &programs {
+        // Места в рейтинге направления — для списка зачисленных с фильтром по направлению
+        let mut admitted: Vec<&Admission> = allocation[&program.code].iter().collect();
+        admitted.sort_by_cached_key(|a| rank_key(program, &applicants, a.idx));
+        admissions.extend(admitted.into_iter().enumerate().map(|(i, a)| AdmissionRecord {
+            id: applicants[a.idx].id,
+            program: &program.code,
+            quota: a.quota.as_deref(),
+            basis: a.basis,
+            rank: i as i32 + 1,
+        }));
+    }
+    apply_allocation(&mut applicants, &allocation);
+    let stats = build_stats(&programs, &applicants);
+    let forecasts = daily_forecasts(pool, &programs, &stages, date, &stats).await?;
+
+    db::save_allocation(pool, snapshot_id, &admissions, date, &stats, &forecasts).await?;
+    Ok(())
 }
 
-pub async fn recalculate_admissions(pool: &SqlitePool, date: &str) {
-    if let Err(e) = db::reset_admission_status(pool).await {
-        println!("Ошибка сброса статусов: {}", e);
-        return;
+/// Прогнозы проходного балла за `date` на сроки приема согласий этапов позже `date`.
+/// История берется до `date` и дополняется статистикой `stats` за `date`.
+async fn daily_forecasts(
+    pool: &SqlitePool,
+    programs: &[Program],
+    stages: &[EnrollmentStage],
+    date: &str,
+    stats: &[ProgramStats],
+) -> Result<Vec<PassingScoreForecast>, sqlx::Error> {
+    let final_dates: BTreeSet<&str> = stages
+        .iter()
+        .map(|s| s.consent_deadline.as_str())
+        .filter(|d| *d > date)
+        .collect();
+    if final_dates.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut history = db::get_history(pool, Some(date)).await?;
+    for s in stats {
+        let points = history.entry(s.program_code.clone()).or_default();
+        points.retain(|h| h.date.as_str() < date);
+        points.push(HistoryPoint::from_stats(date, s));
+    }
+
+    Ok(final_dates
+        .iter()
+        .flat_map(|final_date| passing_score_forecasts(programs, &history, final_date))
+        .collect())
+}
+
+/// Этапы зачисления и зафиксированные зачисления для среза за `date`.
+pub async fn load_stage_plan(pool: &SqlitePool, date: &str) -> Result<StagePlan, sqlx::Error> {
+    let stages = db::list_stages(pool).await?;
+    let enrollments = db::list_enrollments(pool).await?;
+
+    Ok(StagePlan::at(date, &stages, &enrollments))
+}
+
+/// Закрывает этап `stage_code` по срезу `snapshot_id`: пересчет и фиксация зачисленных.
+/// Выполняется одной задачей очереди, чтобы между пересчетом и фиксацией срез
+/// не пересчитала задача другого импорта.
+pub async fn freeze_stage(pool: &SqlitePool, stage_code: &str, snapshot_id: i64, date: &str) -> Result<(), sqlx::Error> {
+    recalculate_admissions(pool, snapshot_id, date).await?;
+    let applicants = db::get_all_applicants(pool, snapshot_id).await?;
+    let enrollments = stage_enrollments(stage_code, &applicants);
+
+    db::freeze_stage(pool, stage_code, date, &enrollments).await
+}
+
+/// Зачисленные в срезе с уже проставленным `current_program` — записи для фиксации этапа `stage_code`.
+pub fn stage_enrollments(stage_code: &str, applicants: &[Applicant]) -> Vec<Enrollment> {
+    applicants
+        .iter()
+        .filter_map(|app| {
+            Some(Enrollment {
+                stage_code: stage_code.to_string(),
+                external_id: app.external_id,
+                program_code: app.current_program.clone()?,
+                quota: app.admitted_quota.clone(),
+                basis: app.admitted_basis.unwrap_or_default(),
+            })
+        })
+        .collect()
+}
+
+/// Конкурсный список направления на бюджет по тем же правилам, что и при распределении:
+/// все, кто указал направление, сначала прошедшие минимальные баллы.
+pub fn program_ranking(program: &Program, applicants: &[Applicant]) -> ProgramRanking {
+    let mut order: Vec<usize> = (0..applicants.len())
+        .filter(|&i| applicants[i].priorities.contains(&program.code))
+        .collect();
+    order.sort_by_cached_key(|&i| (!meets_requirements(program, &applicants[i]), rank_key(program, applicants, i)));
+
+    let passing_score = general_passing_score(program, applicants);
+
+    let entries = order
+        .into_iter()
+        .enumerate()
+        .map(|(pos, idx)| {
+            let app = &applicants[idx];
+            let eligible = meets_requirements(program, app);
+            let score = program_score(program, app);
+            RankingEntry {
+                position: pos as i32 + 1,
+                external_id: app.external_id,
+                full_name: app.full_name.clone(),
+                priority: priority_position(app, &program.code).map_or(0, |i| i as i32 + 1),
+                agreed: app.agreed,
+                eligible,
+                status: ranking_status(program, app, eligible),
+                score,
+                passing_gap: passing_score.filter(|_| !app.bvi).map(|passing| score - passing),
+                criteria: rank_criteria(program, app),
+                current_program: app.current_program.clone(),
+                admitted_quota: app.admitted_quota.clone(),
+                admitted_basis: app.admitted_basis,
+            }
+        })
+        .collect();
+
+    ProgramRanking {
+        program_code: program.code.clone(),
+        criteria: rank_criteria_names(program),
+        passing_score,
+        entries,
+    }
+}
+
+/// Объяснение результата распределения для абитуриента `applicants[idx]`
+/// по каждому его приоритету.
+pub fn explain_admission(idx: usize, programs: &[Program], applicants: &[Applicant]) -> ApplicantDetail {
+    let app = &applicants[idx];
+    let by_code: HashMap<&str, &Program> = programs.iter().map(|p| (p.code.as_str(), p)).collect();
+
+    let mut seen = HashSet::new();
+    let choices: Vec<ChoiceExplanation> = app
+        .priorities
+        .iter()
+        .enumerate()
+        .filter(|(_, code)| seen.insert(code.as_str()))
+        .map(|(i, code)| {
+            let Some(program) = by_code.get(code.as_str()) else {
+                return ChoiceExplanation {
+                    priority: i as i32 + 1,
+                    program_code: code.clone(),
+                    position: None,
+                    score: 0,
+                    passing_score: None,
+                    eligible: false,
+                    fits: false,
+                    status: RankingStatus::NotCompeting,
+                };
+            };
+            let ranking = program_ranking(program, applicants);
+            let entry = ranking
+                .entries
+                .iter()
+                .find(|e| e.external_id == app.external_id)
+                .expect("абитуриент указал направление и есть в его рейтинге");
+            ChoiceExplanation {
+                priority: entry.priority,
+                program_code: code.clone(),
+                position: Some(entry.position),
+                score: entry.score,
+                passing_score: ranking.passing_score,
+                eligible: entry.eligible,
+                fits: entry.status == RankingStatus::Admitted
+                    || (entry.eligible && would_fit(program, applicants, idx)),
+                status: entry.status,
+            }
+        })
+        .collect();
+
+    let explanation = admission_explanation(app, &choices);
+    ApplicantDetail { applicant: app.clone(), choices, explanation }
+}
+
+/// Есть ли для абитуриента место в общем конкурсе направления при текущем
+/// зачислении: свободное место или зачисленный с более низким рейтингом.
+fn would_fit(program: &Program, applicants: &[Applicant], idx: usize) -> bool {
+    let admitted_here = |a: &&Applicant| budget_program(a) == Some(program.code.as_str());
+    let quota_filled = applicants.iter().filter(admitted_here).filter(|a| a.admitted_quota.is_some()).count();
+    let capacity = (program.places.max(0) as usize).saturating_sub(quota_filled);
+
+    let general: Vec<usize> = (0..applicants.len())
+        .filter(|&i| i != idx && admitted_here(&&applicants[i]) && applicants[i].admitted_quota.is_none())
+        .collect();
+    let key = rank_key(program, applicants, idx);
+    general.len() < capacity || general.iter().any(|&i| rank_key(program, applicants, i) > key)
+}
+
+fn choice_reason(choice: &ChoiceExplanation) -> &'static str {
+    match choice.status {
+        _ if choice.position.is_none() => "направления нет в справочнике",
+        RankingStatus::Admitted => "зачислен",
+        RankingStatus::AdmittedHigher => "зачислен на более высокий приоритет",
+        RankingStatus::BelowCut => "места заняты абитуриентами с более высоким рейтингом",
+        RankingStatus::NotCompeting if !choice.eligible => "не пройдены минимальные баллы",
+        RankingStatus::NotCompeting => "нет заявления или согласия на бюджет",
+    }
+}
+
+fn admission_explanation(app: &Applicant, choices: &[ChoiceExplanation]) -> String {
+    let reasons = |list: &[ChoiceExplanation]| {
+        list.iter()
+            .map(|c| format!("{} — {}", c.program_code, choice_reason(c)))
+            .collect::<Vec<_>>()
+            .join("; ")
+    };
+
+    match &app.current_program {
+        Some(code) if app.admitted_basis == Some(FundingBasis::Contract) => {
+            let priority = choices.iter().find(|c| &c.program_code == code).map_or(0, |c| c.priority);
+            format!("Зачислен на {} по договору по {}-му приоритету. Бюджет: {}", code, priority, reasons(choices))
+        }
+        Some(code) => {
+            let pos = choices.iter().position(|c| &c.program_code == code).unwrap_or(0);
+            let mut text = format!("Зачислен на {} по {}-му приоритету", code, choices.get(pos).map_or(0, |c| c.priority));
+            if let Some(quota) = &app.admitted_quota {
+                text.push_str(&format!(" (квота {})", quota));
+            } else if app.bvi {
+                text.push_str(" (без вступительных испытаний)");
+            }
+            if pos > 0 {
+                text.push_str(&format!(". Более высокие приоритеты: {}", reasons(&choices[..pos])));
+            }
+            text
+        }
+        None if !competes_for(app, FundingBasis::Budget) && !competes_for(app, FundingBasis::Contract) => {
+            "Нет согласия на зачисление: в распределении не участвует".to_string()
+        }
+        None if choices.is_empty() => "Не указано ни одного направления".to_string(),
+        None => format!("Не зачислен: {}", reasons(choices)),
     }
+}
+
+/// Направление, на которое абитуриент зачислен на бюджет.
+fn budget_program(app: &Applicant) -> Option<&str> {
+    app.current_program
+        .as_deref()
+        .filter(|_| app.admitted_basis == Some(FundingBasis::Budget))
+}
+
+/// Проходной балл общего конкурса по текущему зачислению (без квот и БВИ).
+fn general_passing_score(program: &Program, applicants: &[Applicant]) -> Option<i32> {
+    applicants
+        .iter()
+        .filter(|a| budget_program(a) == Some(program.code.as_str()))
+        .filter(|a| a.admitted_quota.is_none() && !a.bvi)
+        .map(|a| program_score(program, a))
+        .min()
+}
 
-    let applicants = match db::get_applicants(pool, 100000, 0, None, None, None, None).await {
-        Ok(list) => list,
-        Err(e) => {
-            println!("Ошибка получения списка: {}", e);
-            return;
+fn ranking_status(program: &Program, app: &Applicant, eligible: bool) -> RankingStatus {
+    match budget_program(app) {
+        Some(current) if current == program.code => RankingStatus::Admitted,
+        Some(current) if priority_position(app, current) < priority_position(app, &program.code) => {
+            RankingStatus::AdmittedHigher
         }
+        _ if !competes_for(app, FundingBasis::Budget) || !eligible => RankingStatus::NotCompeting,
+        _ => RankingStatus::BelowCut,
+    }
+}
+
+/// Прогоняет распределение для файла в памяти и сравнивает с актуальным срезом. БД не изменяется.
+/// Сравнение идет со срезом, от которого строился бы импорт за `report_date`.
+pub async fn preview_import(
+    pool: &SqlitePool,
+    report_date: &str,
+    mode: ImportMode,
+    incoming: &[NewApplicant],
+    kept_ids: &[i32],
+) -> Result<ImportPreview, sqlx::Error> {
+    let programs = db::list_programs(pool).await?;
+    let snapshot = db::base_snapshot(pool, report_date).await?;
+    let plan = load_stage_plan(pool, report_date).await?;
+
+    let current = match &snapshot {
+        Some(s) => db::get_all_applicants(pool, s.id).await?,
+        None => Vec::new(),
     };
 
-    let mut active_applicants: Vec<_> = applicants
-        .into_iter()
-        .filter(|a| a.agreed)
+    let mut projected = merge_incoming(&current, incoming, kept_ids, mode);
+    let allocation = allocate_staged(&projected, &programs, &plan);
+    apply_allocation(&mut projected, &allocation);
+
+    let current_stats = build_stats(&programs, &current);
+    let projected_stats = build_stats(&programs, &projected);
+
+    let current_date = snapshot.map(|s| s.report_date);
+    let diff = diff_applicants(current_date.as_deref().unwrap_or(""), "", current, projected);
+
+    let changed: HashSet<i32> = diff.scores_changed.iter().map(|c| c.external_id)
+        .chain(diff.agreed_changed.iter().map(|c| c.external_id))
+        .chain(diff.priorities_changed.iter().map(|c| c.external_id))
         .collect();
 
-    active_applicants.sort_by(|a, b| {
-        b.total_score.cmp(&a.total_score)
-            .then_with(|| b.scores.math.cmp(&a.scores.math))
-            .then_with(|| b.scores.rus.cmp(&a.scores.rus))
-    });
-    
-    let limits = get_program_limits();
-    let mut admission_lists: HashMap<String, Vec<i32>> = HashMap::new();
-    for key in limits.keys() {
-        admission_lists.insert(key.clone(), Vec::new());
+    Ok(ImportPreview {
+        current_date,
+        added: diff.added.len() as i32,
+        removed: diff.removed.len() as i32,
+        changed: changed.len() as i32,
+        program_changed: diff.program_changed.len() as i32,
+        current: current_stats,
+        projected: projected_stats,
+    })
+}
+
+/// Прогоняет распределение до и после гипотетических изменений `changes` и сравнивает
+/// результаты. Оба распределения считаются в памяти по одним правилам, поэтому
+/// разница — только от изменений, а не от устаревшего зачисления в срезе.
+/// БД не изменяется. `Err` — описание неприменимого изменения.
+/// Зачисленные на закрытых этапах (`plan`) сохраняют свои места.
+pub fn simulate(
+    programs: &[Program],
+    applicants: &[Applicant],
+    current_date: Option<String>,
+    plan: &StagePlan,
+    changes: &[SimulationChange],
+) -> Result<SimulationResult, String> {
+    let mut current = applicants.to_vec();
+    let baseline = allocate_staged(&current, programs, plan);
+    apply_allocation(&mut current, &baseline);
+
+    let mut changed_programs = programs.to_vec();
+    let mut simulated = current.clone();
+    for change in changes {
+        apply_change(&mut changed_programs, &mut simulated, change)?;
     }
-    
-    for person in active_applicants {
-        for priority in &person.priorities {
-            if let Some(limit) = limits.get(priority) {
-                let current_list = admission_lists.get_mut(priority).unwrap();
-                if current_list.len() < *limit {
-                    current_list.push(person.id);
-                    let _ = db::set_admission_program(pool, person.id, priority).await;
-                    break;
+
+    let allocation = allocate_staged(&simulated, &changed_programs, plan);
+    apply_allocation(&mut simulated, &allocation);
+
+    let by_code: HashMap<&str, &Program> = changed_programs.iter().map(|p| (p.code.as_str(), p)).collect();
+    let admitted = allocation
+        .iter()
+        .map(|(code, list)| {
+            let program = by_code[code.as_str()];
+            let mut order: Vec<usize> = list.iter().map(|a| a.idx).collect();
+            order.sort_by_cached_key(|&i| rank_key(program, &simulated, i));
+            (code.clone(), order.into_iter().map(|i| simulated[i].external_id).collect())
+        })
+        .collect();
+
+    let current_stats = build_stats(programs, &current);
+    let simulated_stats = build_stats(&changed_programs, &simulated);
+    let diff = diff_applicants(current_date.as_deref().unwrap_or(""), "", current, simulated);
+
+    Ok(SimulationResult {
+        current_date,
+        allocation: admitted,
+        diff,
+        current: current_stats,
+        simulated: simulated_stats,
+    })
+}
+
+fn apply_change(programs: &mut [Program], applicants: &mut Vec<Applicant>, change: &SimulationChange) -> Result<(), String> {
+    let position = |applicants: &[Applicant], id: i32| {
+        applicants
+            .iter()
+            .position(|a| a.external_id == id)
+            .ok_or_else(|| format!("Абитуриент {} не найден", id))
+    };
+
+    match change {
+        SimulationChange::Consent { external_id, agreed, basis } => {
+            let idx = position(applicants, *external_id)?;
+            match basis {
+                FundingBasis::Budget => applicants[idx].agreed = *agreed,
+                FundingBasis::Contract => applicants[idx].contract_agreed = *agreed,
+            }
+        }
+        SimulationChange::Withdraw { external_id } => {
+            let idx = position(applicants, *external_id)?;
+            applicants.remove(idx);
+        }
+        SimulationChange::Places { program_code, delta, basis } => {
+            let program = programs
+                .iter_mut()
+                .find(|p| &p.code == program_code)
+                .ok_or_else(|| format!("Направление {} не найдено", program_code))?;
+            if *basis == FundingBasis::Contract {
+                if program.contract_places + delta < 0 {
+                    return Err(format!("На направлении {} не может быть меньше 0 платных мест", program_code));
                 }
+                program.contract_places += delta;
+                return Ok(());
+            }
+            let places = program.places + delta;
+            let quota_seats: i32 = program.quotas.values().sum();
+            if places < quota_seats {
+                return Err(format!("На направлении {} мест меньше, чем мест по квотам ({})", program_code, quota_seats));
+            }
+            program.places = places;
+        }
+        SimulationChange::AddApplicant {
+            external_id,
+            full_name,
+            scores,
+            achievements,
+            agreed,
+            budget,
+            contract,
+            contract_agreed,
+            bvi,
+            priorities,
+            quota,
+        } => {
+            if applicants.iter().any(|a| a.external_id == *external_id) {
+                return Err(format!("Абитуриент {} уже есть в срезе", external_id));
             }
+            if let Some((subject, _)) = scores.iter().find(|(s, _)| !is_subject_code(s)) {
+                return Err(format!("Некорректный код предмета: '{}'", subject));
+            }
+            if let Some(score) = scores.values().chain([achievements]).find(|v| !(0..=MAX_SCORE).contains(*v)) {
+                return Err(format!("Балл {} вне диапазона 0..{}", score, MAX_SCORE));
+            }
+            if let Some(code) = priorities.iter().find(|c| !programs.iter().any(|p| &&p.code == c)) {
+                return Err(format!("Неизвестный код направления: '{}'", code));
+            }
+            if let Some(q) = quota.as_ref().filter(|q| !QUOTAS.contains(&q.as_str())) {
+                return Err(format!("Неизвестная категория квоты: '{}'", q));
+            }
+
+            let new = NewApplicant {
+                external_id: *external_id,
+                full_name: full_name.clone(),
+                scores: scores.clone(),
+                score_achieve: *achievements,
+                agreed: *agreed,
+                budget: *budget,
+                contract: *contract,
+                contract_agreed: *contract_agreed,
+                bvi: *bvi,
+                priorities: priorities.clone(),
+                quota: quota.clone(),
+            };
+            applicants.push(new.to_applicant(0));
         }
     }
+    Ok(())
+}
 
-    save_statistics(pool, &limits, &admission_lists, date).await;
+/// Набор абитуриентов, который получится после импорта `incoming` поверх `base` в режиме `mode`.
+/// В режиме `Replace` абитуриенты из `kept_ids` (строки файла с ошибками) остаются как в `base`.
+pub fn merge_incoming(
+    base: &[Applicant],
+    incoming: &[NewApplicant],
+    kept_ids: &[i32],
+    mode: ImportMode,
+) -> Vec<Applicant> {
+    match mode {
+        ImportMode::Replace => {
+            let kept: HashSet<i32> = kept_ids.iter().copied().collect();
+            let mut merged: BTreeMap<i32, Applicant> = base
+                .iter()
+                .filter(|a| kept.contains(&a.external_id))
+                .map(|a| (a.external_id, a.clone()))
+                .collect();
+            for a in incoming {
+                merged.insert(a.external_id, a.to_applicant(a.external_id));
+            }
+            merged.into_values().collect()
+        }
+        ImportMode::Merge => {
+            let mut merged: BTreeMap<i32, Applicant> = base.iter().map(|a| (a.external_id, a.clone())).collect();
+            for a in incoming {
+                merged.insert(a.external_id, a.to_applicant(a.external_id));
+            }
+            merged.into_values().collect()
+        }
+        ImportMode::Delete => {
+            let removed: HashSet<i32> = incoming.iter().map(|a| a.external_id).collect();
+            base.iter().filter(|a| !removed.contains(&a.external_id)).cloned().collect()
+        }
+    }
 }
 
-async fn save_statistics(
+/// Наибольший размер набора направлений в пересечениях: число наборов растет
+/// как число сочетаний из приоритетов абитуриента.
+pub const MAX_INTERSECTION_SIZE: usize = 4;
+
+/// Пересечения по актуальному срезу (см. `count_intersections`).
+pub async fn calculate_intersections(
     pool: &SqlitePool,
-    limits: &HashMap<String, usize>,
-    admission_lists: &HashMap<String, Vec<i32>>,
-    date: &str
-) {
-    for (prog_code, admitted_ids) in admission_lists {
-        let total_places = limits.get(prog_code).unwrap_or(&0);
-        let filled = admitted_ids.len();
-
-        let passing_score = if filled > 0 {
-            let last_id = admitted_ids.last().unwrap();
-            let score: i32 = sqlx::query_scalar("SELECT total_score FROM applicants WHERE id = ?")
-                .bind(last_id)
-                .fetch_one(pool)
-                .await
-                .unwrap_or(0);
-            score
-        } else {
-            0
-        };
+    min_size: usize,
+    max_size: usize,
+    agreed_only: bool,
+) -> Result<Vec<IntersectionEntry>, sqlx::Error> {
+    let Some(snapshot) = db::latest_snapshot(pool).await? else {
+        return Ok(Vec::new());
+    };
+    let applicants = db::get_all_applicants(pool, snapshot.id).await?;
 
-        let _ = sqlx::query(
-            r#"
-            INSERT INTO history_stats (record_date, program_code, passing_score, places_filled)
-            VALUES (?, ?, ?, ?)
-            ON CONFLICT(record_date, program_code) DO UPDATE SET
-                passing_score = excluded.passing_score,
-                places_filled = excluded.places_filled
-            "#
-        )
-            .bind(date)
-            .bind(prog_code)
-            .bind(passing_score)
-            .bind(filled as i32)
-            .execute(pool)
-            .await;
-    }
-}
-
-pub async fn calculate_intersections(pool: &SqlitePool) -> HashMap<String, usize> {
-    let applicants = db::get_applicants(pool, 100000, 0, None, None, None, None).await.unwrap_or_default();
-    
-    // Инициализируем счетчики нулями
-    let mut counts = HashMap::new();
-    let keys = [
-        "pm_ivt", "pm_itss", "pm_ib", "ivt_itss", "ivt_ib", "itss_ib",
-        "pm_ivt_itss", "pm_ivt_ib", "ivt_itss_ib", "pm_itss_ib", "all_four"
-    ];
-    for k in keys {
-        counts.insert(k.to_string(), 0);
+    Ok(count_intersections(&applicants, min_size, max_size, agreed_only))
+}
+
+/// Считает пересечения для каждого подмножества направлений, которое встречается
+/// в приоритетах абитуриентов. Размер подмножества ограничен `min_size..=max_size`,
+/// `agreed_only` — только абитуриенты с согласием.
+pub fn count_intersections(
+    applicants: &[Applicant],
+    min_size: usize,
+    max_size: usize,
+    agreed_only: bool,
+) -> Vec<IntersectionEntry> {
+    let mut counts: HashMap<Vec<String>, i32> = HashMap::new();
+
+    for app in applicants.iter().filter(|a| !agreed_only || a.agreed) {
+        let mut codes: Vec<String> = app.priorities.clone();
+        codes.sort();
+        codes.dedup();
+
+        let mut current = Vec::new();
+        collect_subsets(&codes, 0, &mut current, min_size, max_size, &mut counts);
     }
 
-    for app in applicants {
-        let p = &app.priorities;
-        let has_pm = p.contains(&"ПМ".to_string());
-        let has_ivt = p.contains(&"ИВТ".to_string());
-        let has_itss = p.contains(&"ИТСС".to_string());
-        let has_ib = p.contains(&"ИБ".to_string());
+    let mut result: Vec<IntersectionEntry> = counts
+        .into_iter()
+        .map(|(programs, count)| IntersectionEntry { programs, count })
+        .collect();
 
-        // Пары
-        if has_pm && has_ivt { *counts.get_mut("pm_ivt").unwrap() += 1; }
-        if has_pm && has_itss { *counts.get_mut("pm_itss").unwrap() += 1; }
-        if has_pm && has_ib { *counts.get_mut("pm_ib").unwrap() += 1; }
-        if has_ivt && has_itss { *counts.get_mut("ivt_itss").unwrap() += 1; }
-        if has_ivt && has_ib { *counts.get_mut("ivt_ib").unwrap() += 1; }
-        if has_itss && has_ib { *counts.get_mut("itss_ib").unwrap() += 1; }
+    result.sort_by(|a, b| {
+        a.programs.len().cmp(&b.programs.len())
+            .then_with(|| b.count.cmp(&a.count))
+            .then_with(|| a.programs.cmp(&b.programs))
+    });
 
-        // Тройки
-        if has_pm && has_ivt && has_itss { *counts.get_mut("pm_ivt_itss").unwrap() += 1; }
-        if has_pm && has_ivt && has_ib { *counts.get_mut("pm_ivt_ib").unwrap() += 1; }
-        if has_ivt && has_itss && has_ib { *counts.get_mut("ivt_itss_ib").unwrap() += 1; }
-        if has_pm && has_itss && has_ib { *counts.get_mut("pm_itss_ib").unwrap() += 1; }
+    result
+}
+
+/// Обходит подмножества `codes` размером не больше `max_size`, начиная с `start`.
+fn collect_subsets(
+    codes: &[String],
+    start: usize,
+    current: &mut Vec<String>,
+    min_size: usize,
+    max_size: usize,
+    counts: &mut HashMap<Vec<String>, i32>,
+) {
+    if current.len() >= min_size {
+        *counts.entry(current.clone()).or_insert(0) += 1;
+    }
+    if current.len() >= max_size {
+        return;
+    }
 
-        // Четверка
-        if has_pm && has_ivt && has_itss && has_ib { *counts.get_mut("all_four").unwrap() += 1; }
+    for i in start..codes.len() {
+        current.push(codes[i].clone());
+        collect_subsets(codes, i + 1, current, min_size, max_size, counts);
+        current.pop();
     }
+}
+
+pub async fn get_detailed_stats(pool: &SqlitePool, snapshot_id: Option<i64>) -> Result<Vec<ProgramStats>, sqlx::Error> {
+    let applicants = match snapshot_id {
+        Some(id) => db::get_all_applicants(pool, id).await?,
+        None => Vec::new(),
+    };
+    let programs = db::list_programs(pool).await?;
 
-    counts
+    Ok(build_stats(&programs, &applicants))
 }
 
-pub async fn get_detailed_stats(pool: &SqlitePool) -> Vec<crate::models::ProgramStats> {
-    let applicants = db::get_applicants(pool, 100000, 0, None, None, None, None).await.unwrap_or_default();
-    let limits = get_program_limits();
-    
+/// Статистика по направлениям для набора абитуриентов с уже проставленным `current_program`.
+pub fn build_stats(programs: &[Program], applicants: &[Applicant]) -> Vec<ProgramStats> {
     // Подготовка структур для подсчета
-    let mut stats_map: HashMap<String, crate::models::ProgramStats> = HashMap::new();
+    let mut stats_map: HashMap<String, ProgramStats> = HashMap::new();
+    let by_code: HashMap<&str, &Program> = programs.iter().map(|p| (p.code.as_str(), p)).collect();
     
-    for (code, total) in &limits {
-        stats_map.insert(code.clone(), crate::models::ProgramStats {
-            program_name: match code.as_str() {
-                "ПМ" => "Прикладная математика".to_string(),
-                "ИВТ" => "Информатика и ВТ".to_string(),
-                "ИТСС" => "Связь и телекоммуникации".to_string(),
-                "ИБ" => "Информационная безопасность".to_string(),
-                _ => code.to_string(),
-            },
-            program_code: code.clone(),
-            places_total: *total as i32,
+    for program in programs {
+        stats_map.insert(program.code.clone(), ProgramStats {
+            program_name: program.name.clone(),
+            program_code: program.code.clone(),
+            places_total: program.places,
             places_filled: 0,
             passing_score: 0,
             is_shortage: false,
+            general_places: program.places,
+            quotas: program
+                .quotas
+                .iter()
+                .filter(|(_, seats)| **seats > 0)
+                .map(|(quota, seats)| QuotaStats {
+                    quota: quota.clone(),
+                    places_total: *seats,
+                    places_filled: 0,
+                    passing_score: 0,
+                })
+                .collect(),
+            bvi_count: 0,
+            contract: ContractStats {
+                places_total: program.contract_places,
+                places_filled: 0,
+                passing_score: 0,
+                is_shortage: false,
+            },
+            applications: 0,
+            consents: 0,
+            competition_ratio: 0.0,
+            avg_score: 0.0,
+            median_score: 0.0,
             count_priority_1: 0,
             count_priority_2: 0,
             count_priority_3: 0,
@@ -172,8 +641,14 @@ pub async fn get_detailed_stats(pool: &SqlitePool) -> Vec<crate::models::Program
         });
     }
 
+    // Число зачисленных по общему конкурсу и на платные места без БВИ (для проходного балла)
+    let mut general_filled: HashMap<&str, i32> = HashMap::new();
+    let mut contract_filled: HashMap<&str, i32> = HashMap::new();
+    // Конкурсные баллы зачисленных на бюджет без БВИ (для среднего и медианы)
+    let mut budget_scores: HashMap<&str, Vec<i32>> = HashMap::new();
+
     // Проход по абитуриентам
-    for app in &applicants {
+    for app in applicants {
         // Подсчет заявлений по приоритетам
         for (idx, prog_code) in app.priorities.iter().enumerate() {
             if let Some(stat) = stats_map.get_mut(prog_code) {
@@ -186,34 +661,89 @@ pub async fn get_detailed_stats(pool: &SqlitePool) -> Vec<crate::models::Program
                 }
             }
         }
+        let mut seen = HashSet::new();
+        for prog_code in app.priorities.iter().filter(|code| seen.insert(code.as_str())) {
+            if let Some(stat) = stats_map.get_mut(prog_code) {
+                stat.applications += 1;
+                if competes_for(app, FundingBasis::Budget) {
+                    stat.consents += 1;
+                }
+            }
+        }
 
         // Подсчет зачисленных
-        if let Some(current) = &app.current_program {
-            if let Some(stat) = stats_map.get_mut(current) {
-                stat.places_filled += 1;
-                // Определяем каким приоритетом прошел
-                if let Some(idx) = app.priorities.iter().position(|p| p == current) {
-                     match idx {
-                        0 => stat.enrolled_priority_1 += 1,
-                        1 => stat.enrolled_priority_2 += 1,
-                        2 => stat.enrolled_priority_3 += 1,
-                        3 => stat.enrolled_priority_4 += 1,
-                        _ => {}
+        if let Some(current) = &app.current_program
+            && let Some(stat) = stats_map.get_mut(current)
+        {
+            // Платные места считаются отдельно; БВИ в проходной балл не входят
+            if app.admitted_basis == Some(FundingBasis::Contract) {
+                let contract = &mut stat.contract;
+                contract.places_filled += 1;
+                if !app.bvi {
+                    let score = program_score(by_code[current.as_str()], app);
+                    let filled = contract_filled.entry(current.as_str()).or_insert(0);
+                    *filled += 1;
+                    contract.passing_score = if *filled == 1 { score } else { contract.passing_score.min(score) };
+                }
+                continue;
+            }
+
+            stat.places_filled += 1;
+            // Определяем каким приоритетом прошел
+            if let Some(idx) = app.priorities.iter().position(|p| p == current) {
+                 match idx {
+                    0 => stat.enrolled_priority_1 += 1,
+                    1 => stat.enrolled_priority_2 += 1,
+                    2 => stat.enrolled_priority_3 += 1,
+                    3 => stat.enrolled_priority_4 += 1,
+                    _ => {}
+                }
+            }
+            
+            // БВИ занимают места, но в проходной балл не входят
+            if app.bvi {
+                stat.bvi_count += 1;
+                continue;
+            }
+
+            // Минимальный балл (проходной) - балл последнего зачисленного, отдельно по каждой квоте
+            let score = program_score(by_code[current.as_str()], app);
+            budget_scores.entry(current.as_str()).or_default().push(score);
+            match &app.admitted_quota {
+                Some(quota) => {
+                    if let Some(q) = stat.quotas.iter_mut().find(|q| &q.quota == quota) {
+                        q.places_filled += 1;
+                        q.passing_score = if q.places_filled == 1 { score } else { q.passing_score.min(score) };
                     }
                 }
-                
-                // Минимальный балл (проходной) - это будет балл последнего зачисленного (так как сортировка по убыванию)
-                stat.passing_score = app.total_score;
+                None => {
+                    let filled = general_filled.entry(current.as_str()).or_insert(0);
+                    *filled += 1;
+                    stat.passing_score = if *filled == 1 { score } else { stat.passing_score.min(score) };
+                }
             }
         }
     }
 
     // Финализация
-    let mut result: Vec<crate::models::ProgramStats> = stats_map.into_values().collect();
+    let mut result: Vec<ProgramStats> = stats_map.into_values().collect();
     for stat in &mut result {
         stat.is_shortage = stat.places_filled < stat.places_total;
-        if stat.places_filled == 0 {
-            stat.passing_score = 0;
+        stat.contract.is_shortage = stat.contract.places_filled < stat.contract.places_total;
+        // Незанятые места квот вернулись в общий конкурс
+        stat.general_places = stat.places_total - stat.quotas.iter().map(|q| q.places_filled).sum::<i32>();
+        if stat.places_total > 0 {
+            stat.competition_ratio = stat.applications as f64 / stat.places_total as f64;
+        }
+        if let Some(scores) = budget_scores.get_mut(stat.program_code.as_str()) {
+            scores.sort_unstable();
+            let n = scores.len();
+            stat.avg_score = scores.iter().map(|&s| s as f64).sum::<f64>() / n as f64;
+            stat.median_score = if n % 2 == 1 {
+                scores[n / 2] as f64
+            } else {
+                (scores[n / 2 - 1] + scores[n / 2]) as f64 / 2.0
+            };
         }
     }
     
@@ -221,4 +751,307 @@ pub async fn get_detailed_stats(pool: &SqlitePool) -> Vec<crate::models::Program
     result.sort_by(|a, b| a.program_code.cmp(&b.program_code));
     
     result
-}
\ No newline at end of file
+}
+
+/// Поабитуриентное сравнение двух срезов. Абитуриенты сопоставляются по `external_id`.
+pub fn diff_applicants(from_date: &str, to_date: &str, before: Vec<Applicant>, after: Vec<Applicant>) -> SnapshotDiff {
+    let mut old: BTreeMap<i32, Applicant> = before.into_iter().map(|a| (a.external_id, a)).collect();
+    let new: BTreeMap<i32, Applicant> = after.into_iter().map(|a| (a.external_id, a)).collect();
+
+    let mut diff = SnapshotDiff {
+        from: from_date.to_string(),
+        to: to_date.to_string(),
+        added: Vec::new(),
+        removed: Vec::new(),
+        scores_changed: Vec::new(),
+        agreed_changed: Vec::new(),
+        priorities_changed: Vec::new(),
+        program_changed: Vec::new(),
+    };
+
+    for (id, cur) in new {
+        let Some(prev) = old.remove(&id) else {
+            diff.added.push(cur);
+            continue;
+        };
+
+        if prev.scores != cur.scores {
+            diff.scores_changed.push(change(&cur, prev.scores.clone(), cur.scores.clone()));
+        }
+        if prev.agreed != cur.agreed {
+            diff.agreed_changed.push(change(&cur, prev.agreed, cur.agreed));
+        }
+        if prev.priorities != cur.priorities {
+            diff.priorities_changed.push(change(&cur, prev.priorities.clone(), cur.priorities.clone()));
+        }
+        if prev.current_program != cur.current_program {
+            diff.program_changed.push(change(&cur, prev.current_program.clone(), cur.current_program.clone()));
+        }
+    }
+
+    diff.removed = old.into_values().collect();
+
+    diff
+}
+
+fn change<T>(app: &Applicant, before: T, after: T) -> ApplicantChange<T> {
+    ApplicantChange {
+        external_id: app.external_id,
+        full_name: app.full_name.clone(),
+        before,
+        after,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::tests::{allocate, allocated, applicant, indices, numbered, program};
+
+    #[test]
+    fn tie_break_by_priority_then_external_id() {
+        let mut programs = vec![program("A", 1, &[]), program("B", 1, &[])];
+        programs[0].tie_break = vec!["priority".to_string()];
+        // Равные баллы: на "A" выше тот, у кого "A" — первый приоритет
+        let mut applicants = vec![applicant(70, &["B", "A"], None), applicant(70, &["A", "B"], None)];
+        applicants[0].external_id = 1;
+        applicants[1].external_id = 2;
+
+        let ranking = program_ranking(&programs[0], &applicants);
+        assert_eq!(ranking.criteria, vec!["bvi", "score", "priority"]);
+        assert_eq!(ranking.entries[0].external_id, 2);
+        assert_eq!(ranking.entries[0].criteria, vec![0, 70, 0]);
+        assert_eq!(ranking.entries[1].criteria, vec![0, 70, -1]);
+
+        // На "B" правило одно — балл по математике; при равенстве меньший external_id
+        let allocation = allocate(&applicants, &programs);
+        assert_eq!(indices(&allocation["A"]), vec![1]);
+        assert_eq!(indices(&allocation["B"]), vec![0]);
+    }
+
+    #[test]
+    fn collects_subsets_within_size_bounds() {
+        let codes: Vec<String> = ["A", "B", "C"].map(String::from).to_vec();
+        let subsets = |min_size, max_size| {
+            let mut counts = HashMap::new();
+            collect_subsets(&codes, 0, &mut Vec::new(), min_size, max_size, &mut counts);
+            let mut keys: Vec<String> = counts.into_keys().map(|k| k.join("")).collect();
+            keys.sort();
+            keys
+        };
+
+        assert_eq!(subsets(2, 2), vec!["AB", "AC", "BC"]);
+        assert_eq!(subsets(2, 3), vec!["AB", "ABC", "AC", "BC"]);
+        assert_eq!(subsets(1, 1), vec!["A", "B", "C"]);
+        assert!(subsets(4, 5).is_empty());
+    }
+
+    #[test]
+    fn counts_intersections_with_filters() {
+        let mut applicants = vec![
+            applicant(90, &["A", "B", "C"], None),
+            applicant(80, &["B", "A", "A"], None),
+            applicant(70, &["C", "B"], None),
+        ];
+        applicants[2].agreed = false;
+
+        let rows = |entries: Vec<IntersectionEntry>| -> Vec<(String, i32)> {
+            entries.into_iter().map(|e| (e.programs.join(""), e.count)).collect()
+        };
+        // Повтор направления в приоритетах не создает отдельного набора
+        assert_eq!(
+            rows(count_intersections(&applicants, 2, MAX_INTERSECTION_SIZE, false)),
+            vec![("AB".to_string(), 2), ("BC".to_string(), 2), ("AC".to_string(), 1), ("ABC".to_string(), 1)]
+        );
+        assert_eq!(
+            rows(count_intersections(&applicants, 2, 2, true)),
+            vec![("AB".to_string(), 2), ("AC".to_string(), 1), ("BC".to_string(), 1)]
+        );
+        assert_eq!(rows(count_intersections(&applicants, 3, 3, true)), vec![("ABC".to_string(), 1)]);
+    }
+
+    #[test]
+    fn ranking_reports_status_and_passing_gap() {
+        let programs = vec![program("A", 1, &[]), program("B", 1, &[])];
+        let mut applicants = vec![
+            applicant(90, &["B", "A"], None),
+            applicant(80, &["A"], None),
+            applicant(70, &["A", "B"], None),
+            applicant(95, &["A"], None),
+        ];
+        applicants[3].agreed = false;
+        let applicants = allocated(numbered(applicants), &programs);
+
+        let ranking = program_ranking(&programs[0], &applicants);
+        assert_eq!(ranking.passing_score, Some(80));
+        let rows: Vec<_> = ranking
+            .entries
+            .iter()
+            .map(|e| (e.external_id, e.priority, e.status, e.passing_gap))
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                (3, 1, RankingStatus::NotCompeting, Some(15)),
+                (0, 2, RankingStatus::AdmittedHigher, Some(10)),
+                (1, 1, RankingStatus::Admitted, Some(0)),
+                (2, 1, RankingStatus::BelowCut, Some(-10)),
+            ]
+        );
+    }
+
+    #[test]
+    fn explains_admission_by_priorities() {
+        let mut programs = vec![program("A", 1, &[]), program("B", 1, &[]), program("C", 1, &[])];
+        programs[2].min_scores = BTreeMap::from([("math".to_string(), 90)]);
+        let applicants = numbered(vec![applicant(90, &["A"], None), applicant(80, &["C", "A", "B", "X"], None)]);
+        let applicants = allocated(applicants, &programs);
+
+        let detail = explain_admission(1, &programs, &applicants);
+        let rows: Vec<_> = detail
+            .choices
+            .iter()
+            .map(|c| (c.program_code.as_str(), c.position, c.eligible, c.fits, c.status))
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                ("C", Some(1), false, false, RankingStatus::NotCompeting),
+                ("A", Some(2), true, false, RankingStatus::BelowCut),
+                ("B", Some(1), true, true, RankingStatus::Admitted),
+                ("X", None, false, false, RankingStatus::NotCompeting),
+            ]
+        );
+        assert_eq!(detail.choices[1].passing_score, Some(90));
+        assert_eq!(
+            detail.explanation,
+            "Зачислен на B по 3-му приоритету. Более высокие приоритеты: C — не пройдены минимальные баллы; \
+             A — места заняты абитуриентами с более высоким рейтингом"
+        );
+    }
+
+    #[test]
+    fn simulates_changes_against_current_state() {
+        let programs = vec![program("A", 1, &[])];
+        let mut current = numbered(vec![applicant(70, &["A"], None), applicant(90, &["A"], None)]);
+        current[1].agreed = false;
+        // Зачисление в срезе устарело: базовое распределение пересчитывается в памяти
+        current[1].current_program = Some("A".to_string());
+
+        let changes = vec![
+            SimulationChange::Consent { external_id: 1, agreed: true, basis: FundingBasis::Budget },
+            SimulationChange::Places { program_code: "A".to_string(), delta: 1, basis: FundingBasis::Budget },
+            SimulationChange::AddApplicant {
+                external_id: 2,
+                full_name: String::new(),
+                scores: BTreeMap::from([("math".to_string(), 80)]),
+                achievements: 0,
+                agreed: true,
+                budget: true,
+                contract: false,
+                contract_agreed: false,
+                bvi: false,
+                priorities: vec!["A".to_string()],
+                quota: None,
+            },
+        ];
+        let result = simulate(&programs, &current, None, &StagePlan::default(), &changes).unwrap();
+
+        assert_eq!(result.allocation["A"], vec![1, 2]);
+        let moved: Vec<_> = result.diff.program_changed.iter().map(|c| (c.external_id, c.after.clone())).collect();
+        assert_eq!(moved, vec![(0, None), (1, Some("A".to_string()))]);
+        assert_eq!(result.diff.added[0].current_program.as_deref(), Some("A"));
+        assert_eq!((result.current[0].places_total, result.simulated[0].places_total), (1, 2));
+
+        let unknown = [SimulationChange::Withdraw { external_id: 5 }];
+        assert!(simulate(&programs, &current, None, &StagePlan::default(), &unknown).is_err());
+    }
+
+    #[test]
+    fn diff_reports_changes_by_external_id() {
+        let mut before = numbered(vec![
+            applicant(70, &["A"], None),
+            applicant(80, &["A", "B"], None),
+            applicant(90, &["B"], None),
+            applicant(60, &["A"], None),
+        ]);
+        before[2].current_program = Some("B".to_string());
+
+        let mut after = before.clone();
+        after[0].scores = applicant(75, &[], None).scores;
+        after[1].agreed = false;
+        after[1].priorities = vec!["B".to_string(), "A".to_string()];
+        after[2].current_program = None;
+        after.remove(3);
+        let mut added = applicant(50, &["B"], None);
+        added.external_id = 4;
+        // Порядок строк в срезе не важен, сравнение идет по external_id
+        after.insert(0, added);
+
+        let diff = diff_applicants("2024-07-20", "2024-07-21", before, after);
+        assert_eq!((diff.from.as_str(), diff.to.as_str()), ("2024-07-20", "2024-07-21"));
+        let ids = |apps: &[Applicant]| apps.iter().map(|a| a.external_id).collect::<Vec<_>>();
+        assert_eq!(ids(&diff.added), vec![4]);
+        assert_eq!(ids(&diff.removed), vec![3]);
+
+        assert_eq!(diff.scores_changed.len(), 1);
+        assert_eq!(diff.scores_changed[0].external_id, 0);
+        assert_eq!((diff.scores_changed[0].before.total(), diff.scores_changed[0].after.total()), (70, 75));
+        let agreed: Vec<_> = diff.agreed_changed.iter().map(|c| (c.external_id, c.before, c.after)).collect();
+        assert_eq!(agreed, vec![(1, true, false)]);
+        let priorities: Vec<_> = diff.priorities_changed.iter().map(|c| (c.external_id, c.after.clone())).collect();
+        assert_eq!(priorities, vec![(1, vec!["B".to_string(), "A".to_string()])]);
+        let moved: Vec<_> = diff
+            .program_changed
+            .iter()
+            .map(|c| (c.external_id, c.before.clone(), c.after.clone()))
+            .collect();
+        assert_eq!(moved, vec![(2, Some("B".to_string()), None)]);
+    }
+
+    #[test]
+    fn replace_keeps_applicants_from_rejected_rows() {
+        let base = numbered(vec![applicant(70, &["A"], None), applicant(80, &["A"], None), applicant(90, &["A"], None)]);
+        let incoming = vec![NewApplicant {
+            external_id: 0,
+            full_name: String::new(),
+            scores: BTreeMap::from([("math".to_string(), 75)]),
+            score_achieve: 0,
+            agreed: true,
+            budget: true,
+            contract: false,
+            contract_agreed: false,
+            bvi: false,
+            priorities: vec!["A".to_string()],
+            quota: None,
+        }];
+
+        // Строка с id 2 отклонена: абитуриент остается как был, id 1 в файле нет
+        let merged = merge_incoming(&base, &incoming, &[2], ImportMode::Replace);
+        let rows: Vec<_> = merged.iter().map(|a| (a.external_id, a.scores.total())).collect();
+        assert_eq!(rows, vec![(0, 75), (2, 90)]);
+    }
+
+    #[test]
+    fn stats_report_applications_consents_and_scores() {
+        let programs = vec![program("A", 2, &[]), program("B", 1, &[])];
+        let mut applicants = vec![
+            applicant(90, &["A", "B"], None),
+            applicant(80, &["A", "A"], None),
+            applicant(70, &["B", "A"], None),
+            applicant(60, &["A"], None),
+        ];
+        applicants[3].agreed = false;
+        let applicants = allocated(applicants, &programs);
+
+        let stats = build_stats(&programs, &applicants);
+        let a = &stats[0];
+        // Повтор направления в приоритетах — одно заявление
+        assert_eq!((a.applications, a.consents), (4, 3));
+        assert_eq!(a.competition_ratio, 2.0);
+        assert_eq!((a.avg_score, a.median_score), (85.0, 85.0));
+        let b = &stats[1];
+        assert_eq!((b.applications, b.consents, b.places_filled), (2, 2, 1));
+        assert_eq!(b.median_score, 70.0);
+    }
+}
@@ -2,10 +2,14 @@ mod handlers;
 mod models;
 mod db;
 mod logic;
+mod engine;
+mod forecast;
 mod assets;
+mod jobs;
+mod import;
 
 use axum::{
-    routing::{get, post},
+    routing::{get, post, put},
     Router,
 };
 use std::net::SocketAddr;
@@ -17,6 +21,7 @@ use std::str::FromStr;
 #[derive(Clone)]
 pub struct AppState {
     db: sqlx::SqlitePool,
+    jobs: jobs::JobRegistry,
 }
 
 #[tokio::main]
@@ -41,7 +46,7 @@ async fn main() {
         .await
         .expect("Failed to run migrations");
 
-    let state = AppState { db: db_pool };
+    let state = AppState { db: db_pool, jobs: jobs::JobRegistry::default() };
 
 
     let cors = CorsLayer::new()
@@ -51,11 +56,41 @@ async fn main() {
 
     let app = Router::new()
         .route("/api/import", post(handlers::import_data))
+        .route("/api/import/inspect", post(handlers::inspect_import))
+        .route("/api/import/profiles", get(handlers::list_profiles).post(handlers::create_profile))
+        .route(
+            "/api/import/profiles/{name}",
+            put(handlers::update_profile).delete(handlers::delete_profile),
+        )
         .route("/api/applicants", get(handlers::get_applicants))
+        .route("/api/applicants/{external_id}", get(handlers::get_applicant))
         .route("/api/statistics", get(handlers::get_stats))
         .route("/api/history", get(handlers::get_history))
+        .route("/api/snapshots", get(handlers::list_snapshots))
+        .route("/api/diff", get(handlers::get_diff))
+        .route("/api/jobs/{id}", get(handlers::get_job))
+        .route("/api/snapshots/{date}/recalculate", post(handlers::recalculate_snapshot))
         .route("/api/clear", post(handlers::clear_db))
         .route("/api/intersections", get(handlers::get_intersections))
+        .route("/api/simulate", post(handlers::simulate))
+        .route("/api/forecast/admissions", get(handlers::get_admission_forecast))
+        .route("/api/forecast/passing-scores", get(handlers::get_passing_score_forecast))
+        .route("/api/forecast/passing-scores/accuracy", get(handlers::get_forecast_accuracy))
+        .route("/api/programs", get(handlers::list_programs).post(handlers::create_program))
+        .route(
+            "/api/programs/{code}",
+            put(handlers::update_program).delete(handlers::delete_program),
+        )
+        .route("/api/programs/{code}/ranking", get(handlers::get_program_ranking))
+        .route("/api/stages", get(handlers::list_stages).post(handlers::create_stage))
+        .route(
+            "/api/stages/{code}",
+            put(handlers::update_stage).delete(handlers::delete_stage),
+        )
+        .route(
+            "/api/stages/{code}/freeze",
+            post(handlers::freeze_stage).delete(handlers::unfreeze_stage),
+        )
         .fallback(assets::static_handler)
         .layer(cors)
         .with_state(state);
@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 #[derive(Serialize)]
 pub struct ApplicantListResponse {
@@ -13,34 +14,255 @@ pub struct PaginationMeta {
     pub total_pages: usize,
 }
 
-#[derive(Serialize, Clone)]
+#[derive(Serialize, Clone, Debug)]
 pub struct Applicant {
     pub id: i32,
+    pub external_id: i32,
     pub full_name: String,
+    // Согласие на зачисление на бюджет
     pub agreed: bool,
+    // Основания, на которые подано заявление: бюджет и/или договор (платные места)
+    pub budget: bool,
+    pub contract: bool,
+    // Согласие на зачисление по договору
+    pub contract_agreed: bool,
+    // Без вступительных испытаний: зачисляется раньше всех независимо от баллов
+    pub bvi: bool,
     pub total_score: i32,
     pub scores: Scores,
     pub current_program: Option<String>,
     pub priorities: Vec<String>,
+    // Категория квоты из `QUOTAS`, по которой подано заявление
+    pub quota: Option<String>,
+    // Квота, по которой абитуриент прошел; `None` — общий конкурс
+    pub admitted_quota: Option<String>,
+    // Основание, по которому абитуриент прошел на `current_program`
+    pub admitted_basis: Option<FundingBasis>,
 }
 
-#[derive(Serialize, Clone)]
+/// Основание обучения: бюджетные места или договор (платные места).
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, Hash, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum FundingBasis {
+    #[default]
+    Budget,
+    Contract,
+}
+
+impl FundingBasis {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FundingBasis::Budget => "budget",
+            FundingBasis::Contract => "contract",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "budget" => Some(FundingBasis::Budget),
+            "contract" => Some(FundingBasis::Contract),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Clone, PartialEq, Debug)]
 pub struct Scores {
-    pub math: i32,
-    pub rus: i32,
-    pub phys: i32,
+    // Баллы по предметам: `math`, `rus`, `phys`, `inf` и любые другие коды
+    #[serde(flatten)]
+    pub subjects: BTreeMap<String, i32>,
     pub achievements: i32,
 }
 
+impl Scores {
+    pub fn get(&self, subject: &str) -> Option<i32> {
+        self.subjects.get(subject).copied()
+    }
+
+    /// Общий балл для списков: математика + русский + лучший из остальных предметов + достижения.
+    /// Конкурсный балл на конкретное направление считается по его предметам.
+    pub fn total(&self) -> i32 {
+        let best_other = self
+            .subjects
+            .iter()
+            .filter(|(code, _)| *code != "math" && *code != "rus")
+            .map(|(_, score)| *score)
+            .max()
+            .unwrap_or(0);
+
+        self.get("math").unwrap_or(0) + self.get("rus").unwrap_or(0) + best_other + self.achievements
+    }
+}
+
+/// Категории квот: особое право, целевая, отдельная.
+pub const QUOTAS: [&str; 3] = ["special", "target", "separate"];
+
+// Имена, которые не могут быть кодом предмета: `achievements` — ключ баллов за
+// достижения в `scores`, остальные — поля строки при импорте
+const RESERVED_CODES: [&str; 10] = [
+    "achievements", "id", "name", "achieve", "agreed", "priorities", "quota", "bvi", "funding", "contract_agreed",
+];
+
+/// Код предмета: латинские строчные буквы, цифры и `_`, кроме зарезервированных имен.
+pub fn is_subject_code(code: &str) -> bool {
+    !code.is_empty()
+        && code.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_')
+        && !RESERVED_CODES.contains(&code)
+}
+
+#[derive(Serialize)]
+pub struct SnapshotDiff {
+    pub from: String,
+    pub to: String,
+    pub added: Vec<Applicant>,
+    pub removed: Vec<Applicant>,
+    pub scores_changed: Vec<ApplicantChange<Scores>>,
+    pub agreed_changed: Vec<ApplicantChange<bool>>,
+    pub priorities_changed: Vec<ApplicantChange<Vec<String>>>,
+    pub program_changed: Vec<ApplicantChange<Option<String>>>,
+}
+
+#[derive(Serialize)]
+pub struct ApplicantChange<T> {
+    pub external_id: i32,
+    pub full_name: String,
+    pub before: T,
+    pub after: T,
+}
+
+#[derive(Serialize, Clone)]
+pub struct Snapshot {
+    pub id: i64,
+    pub report_date: String,
+    // Версия за дату: 1 — первый импорт, дальше — повторные импорты за ту же дату
+    pub seq: i64,
+    pub created_at: String,
+    pub applicants_count: i64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Program {
+    pub code: String,
+    pub name: String,
+    // Бюджетные места, включая квоты
+    pub places: i32,
+    // Платные места (по договору): отдельный конкурс после бюджетного
+    #[serde(default)]
+    pub contract_places: i32,
+    // Предметы конкурсного балла. Альтернативы через `|` (`phys|inf`): учитывается лучший
+    // из предметов, по которым пройден минимальный балл
+    #[serde(default = "default_subjects")]
+    pub subjects: Vec<String>,
+    // Минимальный балл по предмету; абитуриенты ниже порога на направление не зачисляются
+    #[serde(default)]
+    pub min_scores: BTreeMap<String, i32>,
+    // Места по квотам (`special`, `target`, `separate`) в счет `places`;
+    // незанятые места квот уходят в общий конкурс
+    #[serde(default)]
+    pub quotas: BTreeMap<String, i32>,
+    // Правила при равенстве конкурсного балла, по порядку: `subject:<код>` — балл по предмету,
+    // `achievements` — индивидуальные достижения, `priority` — более высокий приоритет направления
+    #[serde(default = "default_tie_break")]
+    pub tie_break: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ProgramUpdate {
+    pub name: String,
+    pub places: i32,
+    #[serde(default)]
+    pub contract_places: i32,
+    #[serde(default = "default_subjects")]
+    pub subjects: Vec<String>,
+    #[serde(default)]
+    pub min_scores: BTreeMap<String, i32>,
+    #[serde(default)]
+    pub quotas: BTreeMap<String, i32>,
+    #[serde(default = "default_tie_break")]
+    pub tie_break: Vec<String>,
+}
+
+/// Этап зачисления. Этапы идут по `consent_deadline`; зачисление этапа фиксируется
+/// по последнему срезу не позже срока приема согласий.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct EnrollmentStage {
+    pub code: String,
+    pub name: String,
+    // Срок приема согласий, `YYYY-MM-DD`
+    pub consent_deadline: String,
+    // Доля мест общего конкурса (в %), заполненная к концу этапа нарастающим итогом
+    #[serde(default = "default_places_percent")]
+    pub places_percent: i32,
+    // Приоритетный этап: зачисляются только по квотам и без вступительных испытаний
+    #[serde(default)]
+    pub priority_only: bool,
+    // Дата среза, по которому зафиксировано зачисление; `None` — этап не закрыт
+    #[serde(default)]
+    pub frozen_at: Option<String>,
+    // Зафиксировано зачисленных на этапе
+    #[serde(default)]
+    pub enrolled: i32,
+}
+
+#[derive(Deserialize)]
+pub struct StageUpdate {
+    pub name: String,
+    pub consent_deadline: String,
+    #[serde(default = "default_places_percent")]
+    pub places_percent: i32,
+    #[serde(default)]
+    pub priority_only: bool,
+}
+
+fn default_places_percent() -> i32 {
+    100
+}
+
+/// Зачисление, зафиксированное на закрытом этапе.
+#[derive(Serialize, Clone, Debug)]
+pub struct Enrollment {
+    pub stage_code: String,
+    pub external_id: i32,
+    pub program_code: String,
+    pub quota: Option<String>,
+    pub basis: FundingBasis,
+}
+
+fn default_tie_break() -> Vec<String> {
+    vec!["subject:math".to_string(), "subject:rus".to_string()]
+}
+
+fn default_subjects() -> Vec<String> {
+    vec!["math".to_string(), "rus".to_string(), "phys|inf".to_string()]
+}
+
 #[derive(Serialize)]
 pub struct ProgramStats {
     pub program_name: String,
     pub program_code: String,
+    // Бюджетные места; платные — в `contract`
     pub places_total: i32,
     pub places_filled: i32,
+    // Проходной балл общего конкурса
     pub passing_score: i32,
     pub is_shortage: bool,
 
+    // Места общего конкурса с учетом вернувшихся из квот
+    pub general_places: i32,
+    pub quotas: Vec<QuotaStats>,
+    // Мест занято зачисленными без вступительных испытаний
+    pub bvi_count: i32,
+    pub contract: ContractStats,
+
+    // Заявлений (направление в любом приоритете) и согласий на бюджет среди них
+    pub applications: i32,
+    pub consents: i32,
+    // Заявлений на одно бюджетное место
+    pub competition_ratio: f64,
+    // Средний и медианный конкурсный балл зачисленных на бюджет без БВИ
+    pub avg_score: f64,
+    pub median_score: f64,
+
     // Детализация по приоритетам
     pub count_priority_1: i32,
     pub count_priority_2: i32,
@@ -60,52 +282,393 @@ pub struct ImportResponse {
     pub stats: ImportStats,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub warning: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_id: Option<u64>,
+    // Итог пересчета, если импорт вызван с `?wait=true`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job: Option<crate::jobs::Job>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<RowError>,
+    // Результат `?dry_run=true`: БД не изменялась
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preview: Option<ImportPreview>,
+}
+
+#[derive(Serialize)]
+pub struct ImportPreview {
+    // Дата среза, с которым сравнивается файл
+    pub current_date: Option<String>,
+    pub added: i32,
+    pub removed: i32,
+    pub changed: i32,
+    pub program_changed: i32,
+    pub current: Vec<ProgramStats>,
+    pub projected: Vec<ProgramStats>,
+}
+
+/// Запрос "что будет, если": гипотетические изменения поверх среза `date`.
+#[derive(Deserialize)]
+pub struct SimulationRequest {
+    pub date: Option<String>,
+    pub changes: Vec<SimulationChange>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SimulationChange {
+    // Абитуриент подает (`true`) или отзывает (`false`) согласие на основание `basis`
+    Consent {
+        external_id: i32,
+        agreed: bool,
+        #[serde(default)]
+        basis: FundingBasis,
+    },
+    // Абитуриент забирает документы
+    Withdraw { external_id: i32 },
+    // Число мест направления на основании `basis` меняется на `delta`
+    Places {
+        program_code: String,
+        delta: i32,
+        #[serde(default)]
+        basis: FundingBasis,
+    },
+    // Новый абитуриент
+    AddApplicant {
+        external_id: i32,
+        #[serde(default)]
+        full_name: String,
+        scores: BTreeMap<String, i32>,
+        #[serde(default)]
+        achievements: i32,
+        #[serde(default = "default_true")]
+        agreed: bool,
+        #[serde(default = "default_true")]
+        budget: bool,
+        #[serde(default)]
+        contract: bool,
+        #[serde(default)]
+        contract_agreed: bool,
+        #[serde(default)]
+        bvi: bool,
+        priorities: Vec<String>,
+        #[serde(default)]
+        quota: Option<String>,
+    },
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Serialize)]
+pub struct SimulationResult {
+    // Дата среза, поверх которого применены изменения
+    pub current_date: Option<String>,
+    // Зачисленные (`external_id` в порядке рейтинга) по направлениям после изменений
+    pub allocation: BTreeMap<String, Vec<i32>>,
+    pub diff: SnapshotDiff,
+    pub current: Vec<ProgramStats>,
+    pub simulated: Vec<ProgramStats>,
 }
 
 #[derive(Serialize)]
 pub struct ImportStats {
     pub processed: i32,
+    pub rejected: i32,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Serialize)]
+pub struct RowError {
+    pub line: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column: Option<String>,
+    pub reason: String,
+}
+
+impl RowError {
+    pub fn new(line: u64, column: Option<&str>, reason: String) -> Self {
+        RowError {
+            line,
+            column: column.map(|c| c.to_string()),
+            reason,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportMode {
+    // Срез содержит ровно строки файла
+    #[default]
+    Replace,
+    // Только добавить/обновить строки файла
+    Merge,
+    // Удалить абитуриентов с перечисленными id
+    Delete,
+}
+
+// Строка файла после сопоставления колонок. Значения — строки, чтобы проверка
+// могла указать колонку и причину ошибки
+#[derive(Debug, Default)]
 pub struct CsvApplicant {
-    #[serde(alias = "id", alias = "ID", alias = "Уникальный идентификатор абитуриента (ID)")]
-    pub id: i32,
+    pub id: String,
+    pub name: String,
+    // Код предмета и значение его колонки
+    pub scores: Vec<(String, String)>,
+    pub achieve: String,
+    pub agreed: String,
+    pub priorities: String,
+    pub quota: String,
+    pub bvi: String,
+    pub funding: String,
+    pub contract_agreed: String,
+}
 
-    #[serde(alias = "name", alias = "ФИО", alias = "Full Name")]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ImportProfile {
     pub name: String,
+    // Заголовок в файле -> поле (`id`, `name`, `achieve`, `agreed`, `priorities`) или код предмета (`math`, `inf`, ...)
+    #[serde(default)]
+    pub columns: BTreeMap<String, String>,
+    // Дополнительные значения колонки согласия
+    #[serde(default)]
+    pub agreed_true: Vec<String>,
+    #[serde(default)]
+    pub agreed_false: Vec<String>,
+    // Разделитель приоритетов; по умолчанию `;` или `,`
+    #[serde(default)]
+    pub priority_separator: Option<String>,
+}
 
-    #[serde(alias = "math", alias = "Балл Математика", alias = "Математика")]
-    pub math: i32,
+#[derive(Serialize)]
+pub struct ImportInspection {
+    pub delimiter: String,
+    pub headers: Vec<String>,
+    // Заголовок -> предлагаемое поле
+    pub suggested: BTreeMap<String, String>,
+    pub missing_fields: Vec<String>,
+    pub sample: Vec<Vec<String>>,
+}
 
-    #[serde(alias = "rus", alias = "Балл Русский язык", alias = "Русский язык")]
-    pub rus: i32,
+/// Конкурсный список направления с объяснением места каждого абитуриента.
+#[derive(Serialize)]
+pub struct ProgramRanking {
+    pub program_code: String,
+    // Названия критериев в порядке сравнения: `bvi`, `score`, затем `tie_break` направления
+    pub criteria: Vec<String>,
+    // Проходной балл общего конкурса; `None`, если по нему никто не зачислен
+    pub passing_score: Option<i32>,
+    pub entries: Vec<RankingEntry>,
+}
 
-    #[serde(alias = "phys", alias = "Балл Физика/ИКТ", alias = "Физика", alias = "Информатика")]
-    pub phys: i32,
+#[derive(Serialize)]
+pub struct RankingEntry {
+    pub position: i32,
+    pub external_id: i32,
+    pub full_name: String,
+    // Номер, под которым направление указано в приоритетах (с 1)
+    pub priority: i32,
+    pub agreed: bool,
+    // Проходит минимальные баллы; не прошедшие стоят в конце списка
+    pub eligible: bool,
+    pub status: RankingStatus,
+    pub score: i32,
+    // Конкурсный балл минус проходной; `None` для БВИ и при отсутствии проходного
+    pub passing_gap: Option<i32>,
+    // Значения критериев по порядку `ProgramRanking::criteria`; больше — выше
+    pub criteria: Vec<i32>,
+    pub current_program: Option<String>,
+    pub admitted_quota: Option<String>,
+    pub admitted_basis: Option<FundingBasis>,
+}
 
-    #[serde(alias = "achieve", alias = "Балл за индивидуальные достижения", alias = "ИД")]
-    pub achieve: i32,
+/// Карточка абитуриента с объяснением результата распределения.
+#[derive(Serialize)]
+pub struct ApplicantDetail {
+    pub applicant: Applicant,
+    // По одному элементу на каждое направление из приоритетов, в их порядке
+    pub choices: Vec<ChoiceExplanation>,
+    // Почему абитуриент зачислен на `current_program` или не зачислен никуда
+    pub explanation: String,
+}
 
-    #[serde(alias = "agreed", alias = "Наличие согласия о зачислении в ВУЗе", alias = "Согласие")]
-    pub agreed: String,
+#[derive(Serialize)]
+pub struct ChoiceExplanation {
+    pub priority: i32,
+    pub program_code: String,
+    // Место в конкурсном списке направления; `None`, если направления нет в справочнике
+    pub position: Option<i32>,
+    pub score: i32,
+    pub passing_score: Option<i32>,
+    pub eligible: bool,
+    // Прошел бы на направление, если бы оно было его наивысшим оставшимся приоритетом
+    pub fits: bool,
+    pub status: RankingStatus,
+}
 
-    #[serde(alias = "priorities", alias = "Приоритет ОП", alias = "Приоритеты")]
-    pub priorities: String,
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum RankingStatus {
+    // Зачислен на это направление
+    Admitted,
+    // Зачислен на направление с более высоким приоритетом
+    AdmittedHigher,
+    // Участвует в конкурсе, но не проходит
+    BelowCut,
+    // Нет согласия или не пройдены минимальные баллы
+    NotCompeting,
+}
+
+/// Конкурс на платные места направления.
+#[derive(Serialize)]
+pub struct ContractStats {
+    pub places_total: i32,
+    pub places_filled: i32,
+    pub passing_score: i32,
+    pub is_shortage: bool,
 }
 
 #[derive(Serialize)]
-pub struct IntersectionStats {
-    pub pm_ivt: i32,
-    pub pm_itss: i32,
-    pub pm_ib: i32,
-    pub ivt_itss: i32,
-    pub ivt_ib: i32,
-    pub itss_ib: i32,
+pub struct QuotaStats {
+    pub quota: String,
+    pub places_total: i32,
+    pub places_filled: i32,
+    pub passing_score: i32,
+}
+
+#[derive(Serialize)]
+pub struct IntersectionEntry {
+    pub programs: Vec<String>,
+    pub count: i32,
+}
 
-    pub pm_ivt_itss: i32,
-    pub pm_ivt_ib: i32,
-    pub ivt_itss_ib: i32,
-    pub pm_itss_ib: i32,
-    pub all_four: i32,
-}
\ No newline at end of file
+/// Прогноз зачисления к дате окончания приема согласий (метод Монте-Карло).
+#[derive(Serialize)]
+pub struct AdmissionForecast {
+    // Срез, от которого строится прогноз
+    pub date: String,
+    pub final_date: String,
+    // Вероятность, что абитуриент без согласия подаст его к `final_date`
+    pub consent_probability: f64,
+    // То же по направлению первого приоритета (только достаточно большие группы)
+    pub consent_probability_by_program: BTreeMap<String, f64>,
+    pub runs: u32,
+    pub applicants: Vec<ApplicantForecast>,
+}
+
+#[derive(Serialize)]
+pub struct ApplicantForecast {
+    pub external_id: i32,
+    pub full_name: String,
+    pub agreed: bool,
+    // Вероятность зачисления хоть куда-то
+    pub admitted_probability: f64,
+    // По одному элементу на каждое направление из приоритетов, в их порядке
+    pub choices: Vec<ChoiceForecast>,
+}
+
+#[derive(Serialize)]
+pub struct ChoiceForecast {
+    pub priority: i32,
+    pub program_code: String,
+    // Доля прогонов, в которых абитуриент зачислен на направление (бюджет или договор)
+    pub probability: f64,
+    pub budget_probability: f64,
+}
+
+/// Итог распределения по направлению за дату из `history_stats`.
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct HistoryPoint {
+    pub date: String,
+    #[serde(rename = "score")]
+    pub passing_score: i32,
+    pub places_filled: i32,
+    pub applications: i32,
+    pub consents: i32,
+    pub competition_ratio: f64,
+    pub avg_score: f64,
+    pub median_score: f64,
+    pub count_priority_1: i32,
+    pub count_priority_2: i32,
+    pub count_priority_3: i32,
+    pub count_priority_4: i32,
+    pub enrolled_priority_1: i32,
+    pub enrolled_priority_2: i32,
+    pub enrolled_priority_3: i32,
+    pub enrolled_priority_4: i32,
+}
+
+impl HistoryPoint {
+    /// Точка истории за `date` по статистике направления (как ее сохраняет пересчет).
+    pub fn from_stats(date: &str, stats: &ProgramStats) -> Self {
+        HistoryPoint {
+            date: date.to_string(),
+            passing_score: stats.passing_score,
+            places_filled: stats.places_filled,
+            applications: stats.applications,
+            consents: stats.consents,
+            competition_ratio: stats.competition_ratio,
+            avg_score: stats.avg_score,
+            median_score: stats.median_score,
+            count_priority_1: stats.count_priority_1,
+            count_priority_2: stats.count_priority_2,
+            count_priority_3: stats.count_priority_3,
+            count_priority_4: stats.count_priority_4,
+            enrolled_priority_1: stats.enrolled_priority_1,
+            enrolled_priority_2: stats.enrolled_priority_2,
+            enrolled_priority_3: stats.enrolled_priority_3,
+            enrolled_priority_4: stats.enrolled_priority_4,
+        }
+    }
+}
+
+/// Прогноз проходного балла направления на дату окончания приема согласий.
+#[derive(Serialize, Clone, Debug)]
+pub struct PassingScoreForecast {
+    pub program_code: String,
+    // Последняя дата истории, по которой построен прогноз
+    pub forecast_date: String,
+    pub final_date: String,
+    // Дней истории в подгонке: только дни, когда бюджетные места были заполнены
+    pub points: i32,
+    // Изменение проходного балла в день по тренду
+    pub trend: f64,
+    pub passing_score: Option<i32>,
+    // Границы 95% интервала прогноза; нужны хотя бы 3 дня истории
+    pub low: Option<i32>,
+    pub high: Option<i32>,
+    pub projected_consents: Option<i32>,
+    // Согласий к концу приема меньше, чем мест: проходной балл не сложится
+    pub shortage_expected: bool,
+}
+
+/// Сохраненный прогноз в сравнении с фактическим проходным баллом на `final_date`.
+#[derive(Serialize)]
+pub struct ForecastAccuracy {
+    pub forecast_date: String,
+    pub program_code: String,
+    pub final_date: String,
+    pub passing_score: Option<i32>,
+    pub low: Option<i32>,
+    pub high: Option<i32>,
+    // `None`, пока нет истории за `final_date`
+    pub actual: Option<i32>,
+    // Прогноз минус факт
+    pub error: Option<i32>,
+    pub in_range: Option<bool>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subject_codes_exclude_reserved_names() {
+        for code in ["math", "inf", "phys_2", "a1"] {
+            assert!(is_subject_code(code), "{}", code);
+        }
+        for code in ["", "Math", "мат", "math-1", "achievements", "id", "agreed", "contract_agreed"] {
+            assert!(!is_subject_code(code), "{}", code);
+        }
+    }
+}
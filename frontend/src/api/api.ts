@@ -18,8 +18,10 @@ export async function importList(params: { file: File; date: string }) {
   form.append("file", params.file);
   form.append("date", params.date);
 
+  // wait=true: дашборд перечитывает статистику только после пересчета
   const res = await http.post<ImportResponse>("/api/import", form, {
     headers: { "Content-Type": "multipart/form-data" },
+    params: { wait: true },
   });
   return res.data;
 }
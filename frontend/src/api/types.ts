@@ -1,6 +1,13 @@
 // === Типы для Импорта ===
 export interface ImportStats {
   processed: number;
+  rejected: number;
+}
+
+export interface RowError {
+  line: number;
+  column?: string;
+  reason: string;
 }
 
 export interface ImportResponse {
@@ -8,24 +15,61 @@ export interface ImportResponse {
   message: string;
   stats: ImportStats;
   warning?: string; // Поле для предупреждения о 10%
+  job_id?: number;
+  job?: Job;
+  errors?: RowError[];
+  preview?: ImportPreview;
+}
+
+export interface ImportPreview {
+  current_date: string | null;
+  added: number;
+  removed: number;
+  changed: number;
+  program_changed: number;
+  current: StatsRow[];
+  projected: StatsRow[];
+}
+
+export interface Job {
+  id: number;
+  kind: string;
+  report_date: string;
+  status: "queued" | "running" | "done" | "failed";
+  created_at: string;
+  started_at: string | null;
+  finished_at: string | null;
+  duration_ms: number | null;
+  error: string | null;
 }
 
 // === Типы для Абитуриентов ===
+// Баллы по предметам; несданных предметов в объекте нет
 export interface Scores {
-  math: number;
-  rus: number;
-  phys: number;
+  math?: number;
+  rus?: number;
+  phys?: number;
+  inf?: number;
   achievements: number;
+  [subject: string]: number | undefined;
 }
 
 export interface Applicant {
   id: number;
+  external_id: number;
   full_name: string;
   total_score: number;
-  agreed: boolean;
+  agreed: boolean; // Согласие на бюджет
+  budget: boolean; // Подано на бюджет
+  contract: boolean; // Подано на платные места
+  contract_agreed: boolean; // Согласие по договору
+  bvi: boolean; // Без вступительных испытаний
   current_program: string | null; // Может быть null, если не зачислен
   priorities: string[];
   scores: Scores;
+  quota: QuotaCategory | null; // Категория квоты в заявлении
+  admitted_quota: QuotaCategory | null; // null — прошел по общему конкурсу
+  admitted_basis: FundingBasis | null; // null, если не прошел
 }
 
 export interface PaginationMeta {
@@ -40,14 +84,42 @@ export interface ApplicantsResponse {
 }
 
 // === Типы для Статистики ===
+export type QuotaCategory = 'special' | 'target' | 'separate';
+export type FundingBasis = 'budget' | 'contract';
+
+export interface ContractStats {
+  places_total: number;
+  places_filled: number;
+  passing_score: number;
+  is_shortage: boolean;
+}
+
+export interface QuotaStats {
+  quota: QuotaCategory;
+  places_total: number;
+  places_filled: number;
+  passing_score: number;
+}
+
 export interface StatsRow {
   program_name: string;
   program_code: string;
   places_total: number;
   places_filled: number;
-  passing_score: number;
+  passing_score: number; // Проходной балл общего конкурса
   is_shortage: boolean; // Флаг недобора
 
+  general_places: number; // Места общего конкурса с учетом вернувшихся из квот
+  quotas: QuotaStats[];
+  bvi_count: number; // Мест занято БВИ
+  contract: ContractStats; // Конкурс на платные места
+
+  applications: number; // Указавших направление в любом приоритете
+  consents: number; // Из них с согласием на бюджет
+  competition_ratio: number; // Заявлений на бюджетное место
+  avg_score: number; // Средний балл зачисленных на бюджет без БВИ
+  median_score: number;
+
   // Детализация по приоритетам
   count_priority_1: number;
   count_priority_2: number;
@@ -65,22 +137,133 @@ export type StatisticsResponse = StatsRow[];
 // === Типы для Истории (Графиков) ===
 export interface HistoryPoint {
   date: string;
+  score: number; // Проходной балл
+  places_filled: number;
+  applications: number;
+  consents: number;
+  competition_ratio: number;
+  avg_score: number;
+  median_score: number;
+  count_priority_1: number;
+  count_priority_2: number;
+  count_priority_3: number;
+  count_priority_4: number;
+  enrolled_priority_1: number;
+  enrolled_priority_2: number;
+  enrolled_priority_3: number;
+  enrolled_priority_4: number;
+}
+
+export interface IntersectionEntry {
+  programs: string[];
+  count: number;
+}
+
+export type IntersectionStats = IntersectionEntry[];
+
+export type HistoryResponse = Record<string, HistoryPoint[]>;
+// === Рейтинг направления ===
+export type RankingStatus = 'admitted' | 'admitted_higher' | 'below_cut' | 'not_competing';
+
+export interface RankingEntry {
+  position: number;
+  external_id: number;
+  full_name: string;
+  priority: number;
+  agreed: boolean;
+  eligible: boolean;
+  status: RankingStatus;
   score: number;
+  passing_gap: number | null; // балл минус проходной
+  criteria: number[]; // значения по порядку ProgramRanking.criteria
+  current_program: string | null;
+  admitted_quota: QuotaCategory | null;
+  admitted_basis: FundingBasis | null;
 }
 
-export interface IntersectionStats {
-  pm_ivt: number;
-  pm_itss: number;
-  pm_ib: number;
-  ivt_itss: number;
-  ivt_ib: number;
-  itss_ib: number;
+export interface ProgramRanking {
+  program_code: string;
+  criteria: string[]; // "bvi", "score", затем правила tie_break направления
+  passing_score: number | null;
+  entries: RankingEntry[];
+}
+
+// === Карточка абитуриента ===
+export interface ChoiceExplanation {
+  priority: number;
+  program_code: string;
+  position: number | null; // null — направления нет в справочнике
+  score: number;
+  passing_score: number | null;
+  eligible: boolean;
+  fits: boolean;
+  status: RankingStatus;
+}
+
+export interface ApplicantDetail {
+  applicant: Applicant;
+  choices: ChoiceExplanation[];
+  explanation: string;
+}
 
-  pm_ivt_itss: number;
-  pm_ivt_ib: number;
-  ivt_itss_ib: number;
-  pm_itss_ib: number;
-  all_four: number;
+// === Этапы зачисления ===
+export interface EnrollmentStage {
+  code: string;
+  name: string;
+  consent_deadline: string; // YYYY-MM-DD
+  places_percent: number; // Доля мест общего конкурса к концу этапа, нарастающим итогом
+  priority_only: boolean; // Только квоты и БВИ
+  frozen_at: string | null; // Дата среза, по которому закрыт этап
+  enrolled: number;
 }
 
-export type HistoryResponse = Record<string, HistoryPoint[]>;
\ No newline at end of file
+// === Прогноз зачисления ===
+export interface ChoiceForecast {
+  priority: number;
+  program_code: string;
+  probability: number; // Бюджет или договор
+  budget_probability: number;
+}
+
+export interface ApplicantForecast {
+  external_id: number;
+  full_name: string;
+  agreed: boolean;
+  admitted_probability: number; // Зачислен хоть куда-то
+  choices: ChoiceForecast[];
+}
+
+export interface AdmissionForecast {
+  date: string;
+  final_date: string;
+  consent_probability: number;
+  consent_probability_by_program: Record<string, number>;
+  runs: number;
+  applicants: ApplicantForecast[];
+}
+
+// === Прогноз проходного балла ===
+export interface PassingScoreForecast {
+  program_code: string;
+  forecast_date: string; // Последний день истории
+  final_date: string;
+  points: number; // Дней в подгонке
+  trend: number; // Баллов в день
+  passing_score: number | null;
+  low: number | null; // 95% интервал, от 3 дней истории
+  high: number | null;
+  projected_consents: number | null;
+  shortage_expected: boolean;
+}
+
+export interface ForecastAccuracy {
+  forecast_date: string;
+  program_code: string;
+  final_date: string;
+  passing_score: number | null;
+  low: number | null;
+  high: number | null;
+  actual: number | null; // null, пока нет истории за final_date
+  error: number | null; // Прогноз минус факт
+  in_range: boolean | null;
+}
@@ -287,12 +287,9 @@ export function DashboardPage() {
                 <Text fw={700} size="sm">Пересечения (2 ОП)</Text>
                 <Table withTableBorder striped>
                   <Table.Tbody>
-                    <Table.Tr><Table.Td>ПМ + ИВТ</Table.Td><Table.Td>{interQ.data?.pm_ivt}</Table.Td></Table.Tr>
-                    <Table.Tr><Table.Td>ПМ + ИТСС</Table.Td><Table.Td>{interQ.data?.pm_itss}</Table.Td></Table.Tr>
-                    <Table.Tr><Table.Td>ПМ + ИБ</Table.Td><Table.Td>{interQ.data?.pm_ib}</Table.Td></Table.Tr>
-                    <Table.Tr><Table.Td>ИВТ + ИТСС</Table.Td><Table.Td>{interQ.data?.ivt_itss}</Table.Td></Table.Tr>
-                    <Table.Tr><Table.Td>ИВТ + ИБ</Table.Td><Table.Td>{interQ.data?.ivt_ib}</Table.Td></Table.Tr>
-                    <Table.Tr><Table.Td>ИТСС + ИБ</Table.Td><Table.Td>{interQ.data?.itss_ib}</Table.Td></Table.Tr>
+                    {(interQ.data ?? []).filter((e) => e.programs.length === 2).map((e) => (
+                      <Table.Tr key={e.programs.join("+")}><Table.Td>{e.programs.join(" + ")}</Table.Td><Table.Td>{e.count}</Table.Td></Table.Tr>
+                    ))}
                   </Table.Tbody>
                 </Table>
               </Card>
@@ -300,11 +297,9 @@ export function DashboardPage() {
                 <Text fw={700} size="sm">Пересечения (3+ ОП)</Text>
                 <Table withTableBorder striped>
                   <Table.Tbody>
-                    <Table.Tr><Table.Td>ПМ + ИВТ + ИТСС</Table.Td><Table.Td>{interQ.data?.pm_ivt_itss}</Table.Td></Table.Tr>
-                    <Table.Tr><Table.Td>ПМ + ИВТ + ИБ</Table.Td><Table.Td>{interQ.data?.pm_ivt_ib}</Table.Td></Table.Tr>
-                    <Table.Tr><Table.Td>ПМ + ИТСС + ИБ</Table.Td><Table.Td>{interQ.data?.pm_itss_ib}</Table.Td></Table.Tr>
-                    <Table.Tr><Table.Td>ИВТ + ИТСС + ИБ</Table.Td><Table.Td>{interQ.data?.ivt_itss_ib}</Table.Td></Table.Tr>
-                    <Table.Tr><Table.Td fw={700}>Все 4 направления</Table.Td><Table.Td fw={700}>{interQ.data?.all_four}</Table.Td></Table.Tr>
+                    {(interQ.data ?? []).filter((e) => e.programs.length > 2).map((e) => (
+                      <Table.Tr key={e.programs.join("+")}><Table.Td>{e.programs.join(" + ")}</Table.Td><Table.Td>{e.count}</Table.Td></Table.Tr>
+                    ))}
                   </Table.Tbody>
                 </Table>
               </Card>
@@ -342,7 +337,9 @@ export function DashboardPage() {
                   {(applicantsQ.data?.data ?? []).map((a) => (
                     <Table.Tr key={a.id}>
                       <Table.Td>
-                        <Text size="sm" fw={500} style={{ lineHeight: 1.2 }}>{a.full_name}</Text>
+                        <Text size="sm" fw={500} style={{ lineHeight: 1.2 }}>
+                          {a.full_name} {a.bvi && <Badge color="grape" size="xs">БВИ</Badge>}
+                        </Text>
 
                         <Text size="xs" c={a.agreed ? "green" : "dimmed"} fw={a.agreed ? 700 : 400} mb={4}>
                           ID: {a.id} {a.agreed ? '(Согласие)' : ''}
@@ -371,9 +368,10 @@ export function DashboardPage() {
                       <Table.Td style={{ textAlign: 'right', verticalAlign: 'top' }}>
                         <Text fw={700}>{a.total_score}</Text>
                         <Stack gap={0} mt={4}>
-                          <Text size="10px" c="dimmed">М: {a.scores.math}</Text>
-                          <Text size="10px" c="dimmed">Р: {a.scores.rus}</Text>
-                          <Text size="10px" c="dimmed">Ф: {a.scores.phys}</Text>
+                          <Text size="10px" c="dimmed">М: {a.scores.math ?? '—'}</Text>
+                          <Text size="10px" c="dimmed">Р: {a.scores.rus ?? '—'}</Text>
+                          {a.scores.phys !== undefined && <Text size="10px" c="dimmed">Ф: {a.scores.phys}</Text>}
+                          {a.scores.inf !== undefined && <Text size="10px" c="dimmed">И: {a.scores.inf}</Text>}
                         </Stack>
                       </Table.Td>
                     </Table.Tr>
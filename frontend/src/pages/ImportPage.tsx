@@ -116,6 +116,9 @@ export function ImportPage() {
               "application/json",
               "text/plain",                // Часто CSV определяется как простой текст
               "application/vnd.ms-excel",  // Если установлен Excel
+              "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+              "application/vnd.oasis.opendocument.spreadsheet",
+              "text/tab-separated-values",
               "application/csv",
               "text/x-csv"
             ]}
@@ -123,7 +126,7 @@ export function ImportPage() {
           >
             <Stack align="center" gap={6} py="xl" style={{ minHeight: 120, justifyContent: 'center' }}>
               <IconUpload size={34} style={{ opacity: 0.7 }} />
-              <Text fw={600}>Drag & drop CSV файл сюда</Text>
+              <Text fw={600}>Drag & drop CSV или Excel файл сюда</Text>
               <Text c="dimmed" size="sm">
                 Или кликни, чтобы выбрать файл
               </Text>
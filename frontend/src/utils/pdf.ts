@@ -254,14 +254,14 @@ export async function generateReport({ stats, applicants, intersections, chartEl
     doc.text("Пересечения (выбор нескольких программ)", 14, currentY);
     currentY += 5;
 
-    const interBody = [
-      ["ПМ + ИВТ", intersections.pm_ivt, "ПМ + ИВТ + ИТСС", intersections.pm_ivt_itss],
-      ["ПМ + ИТСС", intersections.pm_itss, "ПМ + ИВТ + ИБ", intersections.pm_ivt_ib],
-      ["ПМ + ИБ", intersections.pm_ib, "ПМ + ИТСС + ИБ", intersections.pm_itss_ib],
-      ["ИВТ + ИТСС", intersections.ivt_itss, "ИВТ + ИТСС + ИБ", intersections.ivt_itss_ib],
-      ["ИВТ + ИБ", intersections.ivt_ib, "Все 4 направления", intersections.all_four],
-      ["ИТСС + ИБ", intersections.itss_ib, "", ""],
-    ];
+    const pairs = intersections.filter((e) => e.programs.length === 2);
+    const larger = intersections.filter((e) => e.programs.length > 2);
+    const interBody = Array.from({ length: Math.max(pairs.length, larger.length) }, (_, i) => [
+      pairs[i] ? pairs[i].programs.join(" + ") : "",
+      pairs[i] ? pairs[i].count : "",
+      larger[i] ? larger[i].programs.join(" + ") : "",
+      larger[i] ? larger[i].count : "",
+    ]);
 
     autoTable(doc, {
       startY: currentY,